@@ -3,18 +3,125 @@ use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
 
+#[cfg(feature = "graphics")]
+#[path = "src/render/vertex_layout.rs"]
+mod vertex_layout;
+
 fn main() -> Result<()> {
-    println!("cargo:rerun-if-changed=res/*");
-    println!("cargo:rerun-if-changed=shaders/*");
+    println!("cargo:rerun-if-changed=res");
+    println!("cargo:rerun-if-changed=shaders");
+    println!("cargo:rerun-if-changed=src/render/vertex_layout.rs");
 
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
-    let paths_from_copy = vec!["res/", "shaders/"];
-    match copy_items(&paths_from_copy, out_dir, &copy_options) {
+    let paths_from_copy = vec!["res/"];
+    match copy_items(&paths_from_copy, &out_dir, &copy_options) {
         Ok(_) => {}
         Err(e) => eprintln!("{}", e),
     }
 
+    #[cfg(feature = "graphics")]
+    shaders::compile_all("shaders", &std::path::Path::new(&out_dir).join("shaders"))?;
+
     Ok(())
 }
+
+/// Compiles the GLSL in `shaders/` to SPIR-V, so the game does not need a
+/// shader compiler at runtime.
+#[cfg(feature = "graphics")]
+mod shaders {
+    use super::vertex_layout::{self, Attribute};
+    use eyre::*;
+    use shader_build::{Compiler, ShaderKind};
+    use std::path::Path;
+
+    /// Writes `<name>.spv` into `out_dir` for every shader in `src_dir`.
+    /// Every error is printed before the build fails.
+    pub fn compile_all(src_dir: &str, out_dir: &Path) -> Result<()> {
+        check_layout()?;
+        std::fs::create_dir_all(out_dir)?;
+        let mut compiler =
+            Compiler::new().ok_or_else(|| eyre!("Failed to create the shader compiler."))?;
+        let mut failed = Vec::new();
+        for path in glob::glob(&format!("{}/*", src_dir))? {
+            let path = path?;
+            let kind = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("vert") => ShaderKind::Vertex,
+                Some("frag") => ShaderKind::Fragment,
+                Some("comp") => ShaderKind::Compute,
+                _ => continue,
+            };
+            let name = path.to_string_lossy().into_owned();
+            let source = std::fs::read_to_string(&path)?;
+            let spirv = compiler
+                .compile_into_spirv(&source, kind, &name, "main", None)
+                .map_err(|e| vec![e.to_string()])
+                .and_then(|spirv| match kind {
+                    ShaderKind::Vertex => {
+                        vertex_layout::check_vertex_inputs(&name, &source).map(|_| spirv)
+                    }
+                    _ => Ok(spirv),
+                });
+            match spirv {
+                Ok(spirv) => {
+                    for warning in spirv.get_warning_messages().lines() {
+                        println!("cargo:warning={}", warning);
+                    }
+                    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                    std::fs::write(
+                        out_dir.join(format!("{}.spv", file_name)),
+                        spirv.as_binary_u8(),
+                    )?;
+                }
+                Err(errors) => {
+                    for error in errors {
+                        eprintln!("{}", error);
+                    }
+                    failed.push(name);
+                }
+            }
+        }
+        ensure!(
+            failed.is_empty(),
+            "Failed to compile {}, see the errors above.",
+            failed.join(", ")
+        );
+        Ok(())
+    }
+
+    /// Inputs of one buffer must not overlap, and each location may only be
+    /// provided once.
+    fn check_layout() -> Result<()> {
+        for (buffer, attributes) in &[
+            ("Vertex", &vertex_layout::VERTEX[..]),
+            ("QuadInstance", &vertex_layout::INSTANCE[..]),
+        ] {
+            let mut sorted: Vec<&Attribute> = attributes.iter().collect();
+            sorted.sort_by_key(|attribute| attribute.offset);
+            for pair in sorted.windows(2) {
+                ensure!(
+                    pair[0].offset + pair[0].format.size() <= pair[1].offset,
+                    "Locations {} and {} of {} overlap in src/render/vertex_layout.rs.",
+                    pair[0].location,
+                    pair[1].location,
+                    buffer
+                );
+            }
+        }
+        let mut locations: Vec<u32> = vertex_layout::VERTEX
+            .iter()
+            .chain(vertex_layout::INSTANCE.iter())
+            .map(|attribute| attribute.location)
+            .collect();
+        locations.sort_unstable();
+        for pair in locations.windows(2) {
+            ensure!(
+                pair[0] != pair[1],
+                "Location {} is provided twice in src/render/vertex_layout.rs.",
+                pair[0]
+            );
+        }
+        Ok(())
+    }
+}
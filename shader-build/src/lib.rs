@@ -0,0 +1,8 @@
+//! shaderc for the build script of gnip-gnop.
+//!
+//! Cargo turns an optional dependency on in `[dependencies]` and
+//! `[build-dependencies]` together, and refuses the same crate under two
+//! names. The build script gets shaderc through this crate instead, so the
+//! game itself only links shaderc with the `hot-reload` feature.
+
+pub use shaderc::*;
@@ -1,9 +1,13 @@
 #version 450
 
 layout(location=0) in vec2 vTexCoord;
+layout(location=1) in vec4 vColor;
 
 layout(location=0) out vec4 f_color;
 
+layout(set=1, binding=0) uniform texture2D t_atlas;
+layout(set=1, binding=1) uniform sampler s_atlas;
+
 void main() {
-    f_color = vec4(0.3, 0.2, 0.1, 1.0);
+    f_color = texture(sampler2D(t_atlas, s_atlas), vTexCoord) * vColor;
 }
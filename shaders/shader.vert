@@ -3,9 +3,25 @@
 layout(location = 0) in vec2 a_position;
 layout(location=1) in vec2 aTexCoord;
 
+layout(location=2) in vec2 i_position;
+layout(location=3) in vec2 i_size;
+layout(location=4) in vec4 i_color;
+layout(location=5) in float i_rotation;
+layout(location=6) in vec4 i_uv_rect;
+
 layout(location=0) out vec2 vTexCoord;
+layout(location=1) out vec4 vColor;
+
+layout(set=0, binding=0) uniform Camera {
+    mat4 u_view_proj;
+};
 
 void main() {
-    gl_Position = vec4(a_position, 0.0, 1.0);
-    vTexCoord = aTexCoord;
+    vec2 scaled = a_position * i_size;
+    float c = cos(i_rotation);
+    float s = sin(i_rotation);
+    vec2 rotated = vec2(scaled.x * c - scaled.y * s, scaled.x * s + scaled.y * c);
+    gl_Position = u_view_proj * vec4(rotated + i_position, 0.0, 1.0);
+    vTexCoord = i_uv_rect.xy + aTexCoord * i_uv_rect.zw;
+    vColor = i_color;
 }
@@ -0,0 +1,131 @@
+//! Sound effects for the events the systems push into the event queue.
+
+use crate::include_bytes_from_outdir;
+use crate::input;
+use crate::state;
+use crate::system::System;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Sound {
+    Bounce,
+    Focus,
+    Button,
+    Score,
+}
+
+impl Sound {
+    pub fn from_event(event: &state::Event) -> Option<Self> {
+        match event {
+            state::Event::BallBounce(_) => Some(Sound::Bounce),
+            state::Event::FocusChanged => Some(Sound::Focus),
+            state::Event::ButtonPressed => Some(Sound::Button),
+            state::Event::Score(_) => Some(Sound::Score),
+            state::Event::SaveAndQuit
+            | state::Event::ResumeSavedMatch
+            | state::Event::SettingsChanged(_) => None,
+        }
+    }
+
+    pub fn bytes(self) -> &'static [u8] {
+        match self {
+            Sound::Bounce => include_bytes_from_outdir!("/res/sounds/bounce.wav"),
+            Sound::Focus => include_bytes_from_outdir!("/res/sounds/focus.wav"),
+            Sound::Button => include_bytes_from_outdir!("/res/sounds/button.wav"),
+            Sound::Score => include_bytes_from_outdir!("/res/sounds/score.wav"),
+        }
+    }
+}
+
+pub trait AudioBackend {
+    /// Plays `sound` scaled by `volume`, from 0 to 1.
+    fn play(&self, sound: Sound, volume: f32);
+}
+
+/// Swallows every sound. Used when there is no output device, and for
+/// headless runs.
+pub struct NullBackend;
+
+impl AudioBackend for NullBackend {
+    fn play(&self, _sound: Sound, _volume: f32) {}
+}
+
+#[cfg(feature = "audio")]
+pub struct RodioBackend {
+    // Dropping the stream stops the playback, so it has to live as long as
+    // the handle.
+    _stream: rodio::OutputStream,
+    handle: rodio::OutputStreamHandle,
+}
+
+#[cfg(feature = "audio")]
+impl RodioBackend {
+    pub fn new() -> eyre::Result<Self> {
+        let (stream, handle) = rodio::OutputStream::try_default()
+            .map_err(|e| eyre::eyre!("Failed to open the default audio device: {:?}", e))?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+        })
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioBackend for RodioBackend {
+    fn play(&self, sound: Sound, volume: f32) {
+        use rodio::Source;
+
+        let source = match rodio::Decoder::new(std::io::Cursor::new(sound.bytes())) {
+            Ok(source) => source,
+            Err(e) => {
+                log::error!("Failed to decode {:?} sound: {:?}", sound, e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .handle
+            .play_raw(source.amplify(volume).convert_samples())
+        {
+            log::error!("Failed to play {:?} sound: {:?}", sound, e);
+        }
+    }
+}
+
+/// Plays a sound for every queued event, at the volume of the settings, and
+/// empties the queue.
+pub struct AudioSystem {
+    backend: Box<dyn AudioBackend>,
+}
+
+impl AudioSystem {
+    pub fn new(backend: Box<dyn AudioBackend>) -> Self {
+        Self { backend }
+    }
+
+    /// Uses the default output device, or stays silent when there is none.
+    pub fn with_default_device() -> Self {
+        #[cfg(feature = "audio")]
+        match RodioBackend::new() {
+            Ok(backend) => return Self::new(Box::new(backend)),
+            Err(e) => log::warn!("{}, sound is disabled.", e),
+        }
+        Self::new(Box::new(NullBackend))
+    }
+}
+
+impl System for AudioSystem {
+    fn update_state(
+        &self,
+        _input: &input::Input,
+        state: &mut state::State,
+        events: &mut Vec<state::Event>,
+        _dt: f32,
+    ) {
+        let volume = state.settings.volume;
+        for event in events.drain(..) {
+            match Sound::from_event(&event) {
+                Some(sound) if volume > 0.0 => self.backend.play(sound, volume),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Keyboard bindings for the input actions, stored in the user config
+//! directory.
+
+use eyre::{ensure, eyre, Result};
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+use crate::config;
+use crate::input::Action;
+use crate::state;
+
+const FILE_NAME: &str = "bindings.toml";
+
+/// Keys bound to every action. An action can have any number of keys, and
+/// missing actions in the file keep their default keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub p1_up: Vec<VirtualKeyCode>,
+    pub p1_down: Vec<VirtualKeyCode>,
+    pub p2_up: Vec<VirtualKeyCode>,
+    pub p2_down: Vec<VirtualKeyCode>,
+    pub confirm: Vec<VirtualKeyCode>,
+    pub pause: Vec<VirtualKeyCode>,
+    pub screenshot: Vec<VirtualKeyCode>,
+    pub quit: Vec<VirtualKeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            p1_up: vec![VirtualKeyCode::W],
+            p1_down: vec![VirtualKeyCode::S],
+            p2_up: vec![VirtualKeyCode::Up],
+            p2_down: vec![VirtualKeyCode::Down],
+            confirm: vec![VirtualKeyCode::Return],
+            pause: vec![VirtualKeyCode::Space],
+            screenshot: vec![VirtualKeyCode::F12],
+            quit: vec![VirtualKeyCode::Escape],
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn load_or_default() -> Self {
+        match config::path(FILE_NAME) {
+            Some(path) => config::load_or_default(&path, "key bindings"),
+            None => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path =
+            config::path(FILE_NAME).ok_or_else(|| eyre!("Failed to find the config directory."))?;
+        config::save(self, &path)
+    }
+
+    pub fn keys(&self, action: Action) -> &[VirtualKeyCode] {
+        match action {
+            Action::P1Up => &self.p1_up,
+            Action::P1Down => &self.p1_down,
+            Action::P2Up => &self.p2_up,
+            Action::P2Down => &self.p2_down,
+            Action::Confirm => &self.confirm,
+            Action::Pause => &self.pause,
+            Action::Screenshot => &self.screenshot,
+            Action::Quit => &self.quit,
+        }
+    }
+
+    fn keys_mut(&mut self, action: Action) -> &mut Vec<VirtualKeyCode> {
+        match action {
+            Action::P1Up => &mut self.p1_up,
+            Action::P1Down => &mut self.p1_down,
+            Action::P2Up => &mut self.p2_up,
+            Action::P2Down => &mut self.p2_down,
+            Action::Confirm => &mut self.confirm,
+            Action::Pause => &mut self.pause,
+            Action::Screenshot => &mut self.screenshot,
+            Action::Quit => &mut self.quit,
+        }
+    }
+
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .find(|&action| self.keys(action).contains(&key))
+    }
+
+    /// Makes `key` the only key of `action`, taking it away from any other
+    /// action so one key never triggers two actions. An action left without
+    /// keys gets the old keys of `action` instead, so nothing, the Confirm
+    /// and Quit keys above all, can become unreachable. Fails when `action`
+    /// had no other key to hand over.
+    pub fn rebind(&mut self, action: Action, key: VirtualKeyCode) -> Result<()> {
+        let old_keys: Vec<_> = self
+            .keys(action)
+            .iter()
+            .copied()
+            .filter(|&k| k != key)
+            .collect();
+        if old_keys.is_empty() {
+            for &other in Action::ALL.iter().filter(|&&other| other != action) {
+                let keys = self.keys(other);
+                ensure!(
+                    !keys.contains(&key) || keys.iter().any(|&k| k != key),
+                    "{:?} is the only key of {}.",
+                    key,
+                    other.label()
+                );
+            }
+        }
+        for &other in Action::ALL.iter().filter(|&&other| other != action) {
+            let keys = self.keys_mut(other);
+            if keys.contains(&key) {
+                keys.retain(|&k| k != key);
+                if keys.is_empty() {
+                    *keys = old_keys.clone();
+                }
+            }
+        }
+        *self.keys_mut(action) = vec![key];
+        Ok(())
+    }
+
+    /// Writes the current keys into the labels of the controls menu.
+    pub fn update_labels(&self, state: &mut state::State) {
+        state.key_labels = Action::ALL
+            .iter()
+            .map(|&action| match self.keys(action) {
+                [] => String::from("-"),
+                keys => keys
+                    .iter()
+                    .map(|key| format!("{:?}", key))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+            .collect();
+        state.refresh_menus();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebind_takes_the_key_from_other_actions() {
+        let mut bindings = KeyBindings {
+            p1_up: vec![VirtualKeyCode::W, VirtualKeyCode::Up],
+            ..KeyBindings::default()
+        };
+        bindings.rebind(Action::P2Up, VirtualKeyCode::W).unwrap();
+        assert_eq!(bindings.p2_up, [VirtualKeyCode::W]);
+        assert_eq!(bindings.p1_up, [VirtualKeyCode::Up]);
+        assert_eq!(bindings.action(VirtualKeyCode::W), Some(Action::P2Up));
+    }
+
+    #[test]
+    fn rebind_swaps_the_only_key_of_confirm() {
+        let mut bindings = KeyBindings::default();
+        bindings
+            .rebind(Action::P1Up, VirtualKeyCode::Return)
+            .unwrap();
+        assert_eq!(bindings.p1_up, [VirtualKeyCode::Return]);
+        assert_eq!(bindings.confirm, [VirtualKeyCode::W]);
+    }
+
+    #[test]
+    fn rebind_never_leaves_quit_without_a_key() {
+        let mut bindings = KeyBindings {
+            p1_up: Vec::new(),
+            ..KeyBindings::default()
+        };
+        assert!(bindings
+            .rebind(Action::P1Up, VirtualKeyCode::Escape)
+            .is_err());
+        assert_eq!(
+            bindings,
+            KeyBindings {
+                p1_up: Vec::new(),
+                ..KeyBindings::default()
+            }
+        );
+    }
+}
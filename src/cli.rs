@@ -0,0 +1,167 @@
+use eyre::*;
+use gnip_gnop::{net, rules::MatchRules, state::Difficulty};
+use std::path::PathBuf;
+
+/// Command line options of the game binary.
+#[derive(Debug)]
+pub struct Args {
+    /// Seed for every random decision of the session. A fresh one is picked
+    /// from the clock when absent.
+    pub seed: Option<u64>,
+    /// Write the input of the session to this file on exit.
+    pub record: Option<PathBuf>,
+    /// Drive the game from a recorded input file instead of the keyboard.
+    pub replay: Option<PathBuf>,
+    /// Run the replay to completion without opening a window.
+    pub headless: bool,
+    /// Final `(player1, player2)` score the headless replay must end with.
+    pub expect_score: Option<(u32, u32)>,
+    /// Difficulty of the computer opponent in 1 player matches.
+    pub difficulty: Difficulty,
+    /// Rules file replacing the rules of the settings.
+    pub rules_file: Option<PathBuf>,
+    /// Single rule flags with their values, see `Args::rules`.
+    pub rule_overrides: Vec<(String, String)>,
+    /// Compare rendered screens against the golden images in this directory.
+    pub golden: Option<PathBuf>,
+    /// Overwrite the golden images instead of comparing against them.
+    pub bless: bool,
+    /// Save a screenshot of this rendered frame.
+    pub screenshot_at_frame: Option<u64>,
+    /// Save every rendered frame as a numbered PNG into this directory.
+    pub record_frames: Option<PathBuf>,
+    /// Wait for a network player on this port or address.
+    pub host: Option<String>,
+    /// Play against the network host at this address.
+    pub join: Option<String>,
+    /// Ticks the own input is held back in a network match.
+    pub input_delay: u8,
+    /// Directory with sprite PNGs drawn instead of the built-in ones.
+    pub skin: Option<PathBuf>,
+    /// Show the GPU buffers created per frame on screen.
+    pub buffer_stats: bool,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self {
+            seed: None,
+            record: None,
+            replay: None,
+            headless: false,
+            expect_score: None,
+            difficulty: Difficulty::Normal,
+            rules_file: None,
+            rule_overrides: Vec::new(),
+            golden: None,
+            bless: false,
+            screenshot_at_frame: None,
+            record_frames: None,
+            host: None,
+            join: None,
+            input_delay: net::DEFAULT_INPUT_DELAY,
+            skin: None,
+            buffer_stats: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => parsed.seed = Some(value(&arg, args.next())?.parse()?),
+                "--record" => parsed.record = Some(value(&arg, args.next())?.into()),
+                "--replay" => parsed.replay = Some(value(&arg, args.next())?.into()),
+                "--headless" => parsed.headless = true,
+                "--golden" => parsed.golden = Some(value(&arg, args.next())?.into()),
+                "--bless" => parsed.bless = true,
+                "--screenshot-at-frame" => {
+                    parsed.screenshot_at_frame = Some(value(&arg, args.next())?.parse()?)
+                }
+                "--record-frames" => parsed.record_frames = Some(value(&arg, args.next())?.into()),
+                "--host" => parsed.host = Some(value(&arg, args.next())?),
+                "--join" => parsed.join = Some(value(&arg, args.next())?),
+                "--input-delay" => parsed.input_delay = value(&arg, args.next())?.parse()?,
+                "--skin" => parsed.skin = Some(value(&arg, args.next())?.into()),
+                "--buffer-stats" => parsed.buffer_stats = true,
+                "--difficulty" => parsed.difficulty = value(&arg, args.next())?.parse()?,
+                "--rules" => parsed.rules_file = Some(value(&arg, args.next())?.into()),
+                "--win-by-two" | "--classic-physics" => {
+                    parsed.rule_overrides.push((arg, String::new()))
+                }
+                "--points-to-win" | "--serve-delay" | "--game-over-delay" | "--serve"
+                | "--bounce-jitter" | "--speed-up-per-hit" | "--max-ball-speed" => {
+                    let value = value(&arg, args.next())?;
+                    parsed.rule_overrides.push((arg, value));
+                }
+                "--expect-score" => {
+                    parsed.expect_score = Some(parse_score(&value(&arg, args.next())?)?)
+                }
+                _ => bail!("Unknown argument: {}", arg),
+            }
+        }
+        ensure!(
+            parsed.host.is_none() || parsed.join.is_none(),
+            "--host and --join can't be used together."
+        );
+        Ok(parsed)
+    }
+
+    /// Match rules: `base`, or the `--rules` file when given, with the
+    /// single rule flags applied on top wherever they appeared.
+    pub fn rules(&self, base: &MatchRules) -> Result<MatchRules> {
+        let mut rules = match &self.rules_file {
+            Some(path) => MatchRules::load(path)?,
+            None => base.clone(),
+        };
+        for (flag, value) in &self.rule_overrides {
+            match flag.as_str() {
+                "--win-by-two" => rules.win_by_two = true,
+                "--classic-physics" => rules.classic_physics = true,
+                "--points-to-win" => rules.points_to_win = value.parse()?,
+                "--serve-delay" => rules.serve_delay = value.parse()?,
+                "--game-over-delay" => rules.game_over_delay = value.parse()?,
+                "--serve" => rules.serve = value.parse()?,
+                "--bounce-jitter" => rules.bounce_jitter = value.parse()?,
+                "--speed-up-per-hit" => rules.speed_up_per_hit = value.parse()?,
+                "--max-ball-speed" => rules.max_ball_speed = value.parse()?,
+                _ => unreachable!(),
+            }
+        }
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    /// Address to wait on for `--host`, which takes a bare port as well.
+    pub fn host_addr(&self) -> Option<String> {
+        self.host.as_ref().map(|host| match host.parse::<u16>() {
+            Ok(port) => format!("0.0.0.0:{}", port),
+            Err(_) => host.clone(),
+        })
+    }
+
+    /// Address of the host for `--join`, on the default port when the
+    /// address has none.
+    pub fn join_addr(&self) -> Option<String> {
+        self.join.as_ref().map(|join| {
+            if join.contains(':') {
+                join.clone()
+            } else {
+                format!("{}:{}", join, net::DEFAULT_PORT)
+            }
+        })
+    }
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String> {
+    value.ok_or_else(|| eyre!("Missing value for {}", flag))
+}
+
+/// Parses a score written as `P1:P2`, e.g. `3:1`.
+fn parse_score(score: &str) -> Result<(u32, u32)> {
+    let mut parts = score.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(p1), Some(p2)) => Ok((p1.parse()?, p2.parse()?)),
+        _ => bail!("Expected a score like 3:1, got {}", score),
+    }
+}
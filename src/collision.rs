@@ -0,0 +1,265 @@
+//! Swept collision tests for the ball.
+//!
+//! Instead of checking for overlap once the ball has moved, the ball is
+//! swept along its motion for the tick and the first contact is reported.
+//! A fast ball can't skip over a thin paddle that way.
+
+use crate::math::Vec2;
+
+/// First contact of a swept shape.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hit {
+    /// Fraction of the motion, from 0 to 1, covered before the contact.
+    pub time: f32,
+    /// Unit normal of the surface that was hit, pointing toward the ball.
+    pub normal: Vec2,
+}
+
+/// Sweeps a circle along `motion` against the axis aligned box centred on
+/// `box_center`. Only contacts the circle moves into are reported, so a
+/// circle touching the box while moving away from it is free to go.
+pub fn sweep_circle_aabb(
+    center: Vec2,
+    radius: f32,
+    motion: Vec2,
+    box_center: Vec2,
+    half_size: Vec2,
+) -> Option<Hit> {
+    let local = center - box_center;
+
+    if let Some(normal) = overlap_normal(local, radius, half_size) {
+        return if motion.dot(normal) < 0.0 {
+            Some(Hit { time: 0.0, normal })
+        } else {
+            None
+        };
+    }
+
+    // The circle hits the box where its centre hits the box grown by the
+    // radius with rounded corners. Start with the sharp cornered box.
+    let expanded = half_size + Vec2::broadcast(radius);
+    let (time, normal) = ray_aabb(local, motion, expanded)?;
+
+    let contact = local + motion * time;
+    if let Some(normal) = normal {
+        if contact.x.abs() <= half_size.x || contact.y.abs() <= half_size.y {
+            return Some(Hit { time, normal });
+        }
+    }
+
+    // The ray entered one of the corners, where the grown box is rounded.
+    let corner = Vec2::new(
+        half_size.x.copysign(contact.x),
+        half_size.y.copysign(contact.y),
+    );
+    let time = ray_circle(local - corner, motion, radius)?;
+    let normal = (local + motion * time - corner).normalized();
+    Some(Hit { time, normal })
+}
+
+/// Sweeps a circle along `motion` against the half plane behind the line
+/// of points `p` with `p.dot(normal) == distance`. `normal` is a unit
+/// vector pointing away from the half plane.
+pub fn sweep_circle_plane(
+    center: Vec2,
+    radius: f32,
+    motion: Vec2,
+    normal: Vec2,
+    distance: f32,
+) -> Option<Hit> {
+    let gap = center.dot(normal) - distance - radius;
+    let approach = motion.dot(normal);
+    if approach >= 0.0 {
+        return None;
+    }
+    let time = (gap / -approach).max(0.0);
+    if time > 1.0 {
+        return None;
+    }
+    Some(Hit { time, normal })
+}
+
+/// Mirrors `velocity` on a surface with the unit `normal`.
+pub fn reflect(velocity: Vec2, normal: Vec2) -> Vec2 {
+    velocity - normal * 2.0 * velocity.dot(normal)
+}
+
+/// Direction to push a circle at `local`, relative to the box centre, out
+/// of the box, or `None` when they don't overlap.
+fn overlap_normal(local: Vec2, radius: f32, half_size: Vec2) -> Option<Vec2> {
+    let closest = Vec2::new(
+        local.x.max(-half_size.x).min(half_size.x),
+        local.y.max(-half_size.y).min(half_size.y),
+    );
+    let offset = local - closest;
+    let distance_sq = offset.mag_sq();
+    if distance_sq >= radius * radius {
+        return None;
+    }
+    if distance_sq > 0.0 {
+        return Some(offset / distance_sq.sqrt());
+    }
+
+    // The centre is inside the box, leave through the nearest side.
+    let depth_x = half_size.x - local.x.abs();
+    let depth_y = half_size.y - local.y.abs();
+    if depth_x < depth_y {
+        Some(Vec2::new(1.0f32.copysign(local.x), 0.0))
+    } else {
+        Some(Vec2::new(0.0, 1.0f32.copysign(local.y)))
+    }
+}
+
+/// Slab test of a ray against a box centred on the origin. Returns the
+/// entry time within the ray and the normal of the entry side, which is
+/// `None` when the ray starts inside the box.
+fn ray_aabb(origin: Vec2, motion: Vec2, half_size: Vec2) -> Option<(f32, Option<Vec2>)> {
+    let mut enter = 0.0f32;
+    let mut exit = 1.0f32;
+    let mut normal = None;
+
+    for &(origin, motion, half_size, axis) in &[
+        (origin.x, motion.x, half_size.x, Vec2::unit_x()),
+        (origin.y, motion.y, half_size.y, Vec2::unit_y()),
+    ] {
+        if motion == 0.0 {
+            if origin.abs() > half_size {
+                return None;
+            }
+            continue;
+        }
+        let near = (-half_size.copysign(motion) - origin) / motion;
+        let far = (half_size.copysign(motion) - origin) / motion;
+        if near >= enter {
+            enter = near;
+            normal = Some(axis * -motion.signum());
+        }
+        exit = exit.min(far);
+        if enter > exit {
+            return None;
+        }
+    }
+
+    Some((enter, normal))
+}
+
+/// First time the ray enters a circle of `radius` around the origin.
+fn ray_circle(origin: Vec2, motion: Vec2, radius: f32) -> Option<f32> {
+    let a = motion.mag_sq();
+    let b = origin.dot(motion);
+    let c = origin.mag_sq() - radius * radius;
+    let discriminant = b * b - a * c;
+    if a == 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    let time = (-b - discriminant.sqrt()) / a;
+    if (0.0..=1.0).contains(&time) {
+        Some(time)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_near_vec(actual: Vec2, expected: Vec2) {
+        assert!(
+            (actual - expected).mag() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn fast_ball_does_not_tunnel_through_thin_paddle() {
+        // A tick long enough to carry the ball from one side of the paddle
+        // far past the other.
+        let velocity = Vec2::new(60.0, 0.0);
+        let motion = velocity / 60.0;
+        let hit = sweep_circle_aabb(
+            Vec2::new(-0.5, 0.1),
+            0.05,
+            motion,
+            Vec2::zero(),
+            Vec2::new(0.025, 0.2),
+        )
+        .expect("the ball passes through the paddle");
+
+        // The centre stops at the paddle face grown by the radius.
+        assert_near(hit.time, (0.5 - 0.075) / 1.0);
+        assert_near_vec(hit.normal, Vec2::new(-1.0, 0.0));
+        assert_near_vec(reflect(velocity, hit.normal), Vec2::new(-60.0, 0.0));
+    }
+
+    #[test]
+    fn exact_corner_hit_bounces_back() {
+        let half_size = Vec2::new(0.025, 0.2);
+        let corner = half_size;
+        let velocity = Vec2::new(-1.0, -1.0);
+        let hit = sweep_circle_aabb(
+            corner + Vec2::new(0.5, 0.5),
+            0.05,
+            velocity,
+            Vec2::zero(),
+            half_size,
+        )
+        .expect("the ball misses the corner");
+
+        // The ball touches the corner when its centre is a radius away.
+        assert_near(hit.time, 0.5 - 0.05 / 2.0f32.sqrt());
+        assert_near_vec(hit.normal, Vec2::new(1.0, 1.0).normalized());
+        assert_near_vec(reflect(velocity, hit.normal), Vec2::new(1.0, 1.0));
+    }
+
+    #[test]
+    fn ball_grazing_past_the_corner_is_free() {
+        let half_size = Vec2::new(0.025, 0.2);
+        let hit = sweep_circle_aabb(
+            half_size + Vec2::new(0.5, 0.06),
+            0.05,
+            Vec2::new(-1.0, 0.0),
+            Vec2::zero(),
+            half_size,
+        );
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn wall_hit_reflects_the_vertical_speed() {
+        let velocity = Vec2::new(0.5, 2.0);
+        let hit = sweep_circle_plane(
+            Vec2::new(0.0, 0.9),
+            0.0,
+            velocity * 0.1,
+            -Vec2::unit_y(),
+            -1.0,
+        )
+        .expect("the ball passes through the wall");
+        assert_near(hit.time, 0.5);
+        assert_near_vec(reflect(velocity, hit.normal), Vec2::new(0.5, -2.0));
+    }
+
+    #[test]
+    fn ball_moving_away_from_a_touching_paddle_is_free() {
+        let hit = sweep_circle_aabb(
+            Vec2::new(0.074, 0.0),
+            0.05,
+            Vec2::new(1.0, 0.0),
+            Vec2::zero(),
+            Vec2::new(0.025, 0.2),
+        );
+        assert_eq!(hit, None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Files kept in the user config directory between runs, e.g.
+//! `~/.config/gnip-gnop/settings.toml`, all of them TOML.
+
+use eyre::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::Path;
+#[cfg(feature = "graphics")]
+use std::path::PathBuf;
+
+/// Where the config file `file_name` lives, `None` when the platform has no
+/// config directory.
+#[cfg(feature = "graphics")]
+pub fn path(file_name: &str) -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join(file_name))
+}
+
+/// Reads the TOML file at `path`, falling back to the defaults when it is
+/// missing or broken. `what` names the file's content in the warning.
+pub fn load_or_default<T: DeserializeOwned + Default>(path: &Path, what: &str) -> T {
+    if !path.exists() {
+        log::warn!("No {} at {}, using the defaults.", what, path.display());
+        return T::default();
+    }
+    match std::fs::read_to_string(path)
+        .map_err(Report::from)
+        .and_then(|text| Ok(toml::from_str(&text)?))
+    {
+        Ok(value) => value,
+        Err(e) => {
+            log::warn!(
+                "Invalid {} {}, using the defaults: {}",
+                what,
+                path.display(),
+                e
+            );
+            T::default()
+        }
+    }
+}
+
+/// Writes `value` as TOML to `path`, creating its directory if needed.
+pub fn save<T: Serialize>(value: &T, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, toml::to_string(value)?)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "{}-{}-{}.toml",
+            env!("CARGO_PKG_NAME"),
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("round-trip");
+        let settings = Settings {
+            volume: 0.25,
+            vsync: false,
+            ..Settings::default()
+        };
+        save(&settings, &path).unwrap();
+        let loaded: Settings = load_or_default(&path, "settings");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, settings);
+    }
+
+    #[test]
+    fn missing_or_broken_files_give_the_defaults() {
+        let path = temp_path("broken");
+        let missing: Settings = load_or_default(&path, "settings");
+        assert_eq!(missing, Settings::default());
+
+        std::fs::write(&path, "volume = \"loud\"").unwrap();
+        let broken: Settings = load_or_default(&path, "settings");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(broken, Settings::default());
+    }
+}
@@ -0,0 +1,126 @@
+//! Gamepad input through gilrs, feeding the same `Input` as the keyboard.
+//!
+//! The first two connected pads are assigned to player 1 and player 2 in
+//! that order. A pad that disconnects frees its slot, the next pad that
+//! connects takes it over.
+
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+use crate::input::{Action, Input};
+
+/// Stick values closer to the centre than this are treated as released.
+const DEAD_ZONE: f32 = 0.15;
+
+pub struct Gamepads {
+    gilrs: Gilrs,
+    slots: [Option<GamepadId>; 2],
+}
+
+impl Gamepads {
+    /// Starts listening for pads, or returns `None` when the platform has
+    /// no gamepad support.
+    pub fn new() -> Option<Self> {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                log::warn!("Failed to initialize gamepads: {}", e);
+                return None;
+            }
+        };
+        let mut gamepads = Self {
+            gilrs,
+            slots: [None; 2],
+        };
+        let connected = gamepads
+            .gilrs
+            .gamepads()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for id in connected {
+            gamepads.assign(id);
+        }
+        Some(gamepads)
+    }
+
+    fn assign(&mut self, id: GamepadId) {
+        if self.slots.contains(&Some(id)) {
+            return;
+        }
+        if let Some(slot) = self.slots.iter().position(Option::is_none) {
+            log::info!(
+                "Gamepad {} controls player {}",
+                self.gilrs.gamepad(id).name(),
+                slot + 1
+            );
+            self.slots[slot] = Some(id);
+        }
+    }
+
+    /// Applies every pending gamepad event to `input`.
+    pub fn update(&mut self, input: &mut Input) {
+        while let Some(event) = self.gilrs.next_event() {
+            if let EventType::Connected = event.event {
+                self.assign(event.id);
+                continue;
+            }
+            let player = match self.slots.iter().position(|&slot| slot == Some(event.id)) {
+                Some(player) => player,
+                None => continue,
+            };
+            match event.event {
+                EventType::Disconnected => {
+                    log::info!("Gamepad of player {} disconnected", player + 1);
+                    self.slots[player] = None;
+                    release(input, player);
+                }
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(action) = button_action(button, player) {
+                        input.apply(action, true);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(action) = button_action(button, player) {
+                        input.apply(action, false);
+                    }
+                }
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+                    let value = if value.abs() < DEAD_ZONE { 0.0 } else { value };
+                    let axis = Input::quantize_axis(value);
+                    match player {
+                        0 => input.p1_axis = axis,
+                        _ => input.p2_axis = axis,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn button_action(button: Button, player: usize) -> Option<Action> {
+    match (button, player) {
+        (Button::DPadUp, 0) => Some(Action::P1Up),
+        (Button::DPadDown, 0) => Some(Action::P1Down),
+        (Button::DPadUp, _) => Some(Action::P2Up),
+        (Button::DPadDown, _) => Some(Action::P2Down),
+        (Button::South, _) => Some(Action::Confirm),
+        (Button::Start, _) => Some(Action::Pause),
+        _ => None,
+    }
+}
+
+/// Lets go of everything a disconnected pad was holding.
+fn release(input: &mut Input, player: usize) {
+    match player {
+        0 => {
+            input.apply(Action::P1Up, false);
+            input.apply(Action::P1Down, false);
+            input.p1_axis = 0;
+        }
+        _ => {
+            input.apply(Action::P2Up, false);
+            input.apply(Action::P2Down, false);
+            input.p2_axis = 0;
+        }
+    }
+}
@@ -1,6 +1,49 @@
-use winit::event::{ElementState, VirtualKeyCode};
+#[cfg(feature = "snapshot")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default)]
+/// Everything a player can ask the game to do, independent of the device
+/// it came from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Action {
+    P1Up,
+    P1Down,
+    P2Up,
+    P2Down,
+    Confirm,
+    Pause,
+    Screenshot,
+    Quit,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::P1Up,
+        Action::P1Down,
+        Action::P2Up,
+        Action::P2Down,
+        Action::Confirm,
+        Action::Pause,
+        Action::Screenshot,
+        Action::Quit,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::P1Up => "P1 Up",
+            Action::P1Down => "P1 Down",
+            Action::P2Up => "P2 Up",
+            Action::P2Down => "P2 Down",
+            Action::Confirm => "Confirm",
+            Action::Pause => "Pause",
+            Action::Screenshot => "Screenshot",
+            Action::Quit => "Quit",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct Input {
     pub p1_up_pressed: bool,
     pub p1_down_pressed: bool,
@@ -8,6 +51,10 @@ pub struct Input {
     pub p2_down_pressed: bool,
     pub enter_pressed: bool,
     pub space_pressed: bool,
+    /// Analog paddle control from a gamepad stick, -127 (down) to 127 (up).
+    /// Stored quantized so a recorded session plays back bit-for-bit.
+    pub p1_axis: i8,
+    pub p2_axis: i8,
 }
 
 impl Input {
@@ -15,37 +62,72 @@ impl Input {
         Default::default()
     }
 
-    pub fn update(&mut self, key: &VirtualKeyCode, state: &ElementState) -> bool {
-        let pressed = state == &ElementState::Pressed;
-        match key {
-            VirtualKeyCode::Up => {
-                self.p2_up_pressed = pressed;
-                true
-            }
-            VirtualKeyCode::Down => {
-                self.p2_down_pressed = pressed;
-                true
-            }
-            VirtualKeyCode::W => {
-                self.p1_up_pressed = pressed;
-                true
-            }
-            VirtualKeyCode::S => {
-                self.p1_down_pressed = pressed;
-                true
-            }
-            VirtualKeyCode::Return => {
-                self.enter_pressed = pressed;
-                true
-            }
-            VirtualKeyCode::Space => {
-                self.space_pressed = pressed;
-                true
-            }
-            _ => false,
+    /// Sets the flag `action` maps to. `Screenshot` and `Quit` have no
+    /// flag and are left to the caller.
+    pub fn apply(&mut self, action: Action, pressed: bool) {
+        match action {
+            Action::P1Up => self.p1_up_pressed = pressed,
+            Action::P1Down => self.p1_down_pressed = pressed,
+            Action::P2Up => self.p2_up_pressed = pressed,
+            Action::P2Down => self.p2_down_pressed = pressed,
+            Action::Confirm => self.enter_pressed = pressed,
+            Action::Pause => self.space_pressed = pressed,
+            Action::Screenshot | Action::Quit => {}
+        }
+    }
+
+    /// Packs the flags into a byte, one bit per flag, for replay files. The
+    /// axes are stored separately.
+    pub fn to_bits(&self) -> u8 {
+        self.p1_up_pressed as u8
+            | (self.p1_down_pressed as u8) << 1
+            | (self.p2_up_pressed as u8) << 2
+            | (self.p2_down_pressed as u8) << 3
+            | (self.enter_pressed as u8) << 4
+            | (self.space_pressed as u8) << 5
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            p1_up_pressed: bits & 1 != 0,
+            p1_down_pressed: bits & 1 << 1 != 0,
+            p2_up_pressed: bits & 1 << 2 != 0,
+            p2_down_pressed: bits & 1 << 3 != 0,
+            enter_pressed: bits & 1 << 4 != 0,
+            space_pressed: bits & 1 << 5 != 0,
+            ..Default::default()
         }
     }
 
+    /// Flags that are down now but were up in `prev`.
+    pub fn pressed_since(&self, prev: &Input) -> Input {
+        Input {
+            p1_up_pressed: self.p1_up_pressed && !prev.p1_up_pressed,
+            p1_down_pressed: self.p1_down_pressed && !prev.p1_down_pressed,
+            p2_up_pressed: self.p2_up_pressed && !prev.p2_up_pressed,
+            p2_down_pressed: self.p2_down_pressed && !prev.p2_down_pressed,
+            enter_pressed: self.enter_pressed && !prev.enter_pressed,
+            space_pressed: self.space_pressed && !prev.space_pressed,
+            p1_axis: self.p1_axis,
+            p2_axis: self.p2_axis,
+        }
+    }
+
+    /// Converts a stick value in `[-1, 1]` to the stored axis format.
+    pub fn quantize_axis(value: f32) -> i8 {
+        (value.clamp(-1.0, 1.0) * 127.0).round() as i8
+    }
+
+    /// Paddle movement of player 1 in `[-1, 1]`, positive is up. A stick
+    /// gives proportional speed, keys give full speed.
+    pub fn p1_movement(&self) -> f32 {
+        movement(self.p1_up_pressed, self.p1_down_pressed, self.p1_axis)
+    }
+
+    pub fn p2_movement(&self) -> f32 {
+        movement(self.p2_up_pressed, self.p2_down_pressed, self.p2_axis)
+    }
+
     pub fn ui_up_pressed(&self) -> bool {
         self.p1_up_pressed || self.p2_up_pressed
     }
@@ -54,3 +136,11 @@ impl Input {
         self.p1_down_pressed || self.p2_down_pressed
     }
 }
+
+fn movement(up: bool, down: bool, axis: i8) -> f32 {
+    if axis != 0 {
+        axis as f32 / 127.0
+    } else {
+        up as i32 as f32 - down as i32 as f32
+    }
+}
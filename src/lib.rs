@@ -0,0 +1,34 @@
+//! Ping pong in 2020.
+//!
+//! The game logic lives in [`sim`] and builds without any windowing or GPU
+//! dependencies when the default `graphics` feature is turned off:
+//!
+//! ```text
+//! cargo test --no-default-features
+//! ```
+#![allow(clippy::single_match)]
+
+pub mod audio;
+#[cfg(feature = "graphics")]
+pub mod bindings;
+pub mod collision;
+pub mod config;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+pub mod input;
+pub mod math;
+pub mod menu;
+pub mod net;
+pub mod replay;
+pub mod rng;
+pub mod rules;
+pub mod settings;
+pub mod sim;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+pub mod state;
+pub mod system;
+pub mod util;
+
+#[cfg(feature = "graphics")]
+pub mod render;
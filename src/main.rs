@@ -1,26 +1,90 @@
 #![allow(clippy::single_match)]
 pub(crate) use eyre::*;
 use futures::executor::block_on;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use winit::{
+    dpi::PhysicalSize,
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
-mod input;
-mod math;
-mod render;
-mod state;
-mod system;
-mod util;
+mod cli;
 
-use input::Input;
-use render::Renderer;
-use system::System;
+#[cfg(feature = "gamepad")]
+use gnip_gnop::gamepad::Gamepads;
+#[cfg(feature = "hot-reload")]
+use gnip_gnop::render::hot_reload::ShaderWatcher;
+#[cfg(feature = "snapshot")]
+use gnip_gnop::snapshot;
+use gnip_gnop::{
+    audio::AudioSystem,
+    bindings::KeyBindings,
+    config,
+    input::{Action, Input},
+    net::{MatchSetup, Session, UdpTransport},
+    render::{golden, Renderer},
+    replay::Recording,
+    rules::MatchRules,
+    settings::{self, Setting, Settings},
+    sim::Simulation,
+    state,
+    system::System,
+    util,
+};
+
+const HEADLESS_WIDTH: f32 = 800.0;
+const HEADLESS_HEIGHT: f32 = 600.0;
+/// How long to wait for the other player of a network match.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
 
 fn main() -> Result<()> {
     env_logger::init();
     color_eyre::install()?;
+    let args = cli::Args::parse()?;
+
+    if let Some(dir) = &args.golden {
+        return block_on(golden::run(dir, args.bless));
+    }
+
+    let replay = match &args.replay {
+        Some(path) => Some(Recording::load(path)?),
+        None => None,
+    };
+    if args.headless {
+        let replay = replay
+            .as_ref()
+            .ok_or_else(|| eyre!("--headless requires --replay."))?;
+        return run_headless(&args, replay);
+    }
+
+    let mut seed = match (&replay, args.seed) {
+        (Some(replay), _) => replay.seed,
+        (None, Some(seed)) => seed,
+        (None, None) => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+    let settings = match settings_path() {
+        Some(path) => Settings::load_or_default(path),
+        None => {
+            log::warn!("Failed to find the config directory, using the default settings.");
+            Settings::default()
+        }
+    };
+    let difficulty = replay.as_ref().map_or(args.difficulty, |r| r.difficulty);
+    let mut rules = match &replay {
+        Some(replay) => replay.rules.clone(),
+        None => args.rules(&settings.rules)?,
+    };
+
+    let mut session = match connect(&args, seed, &rules)? {
+        Some((session, setup)) => {
+            seed = setup.seed;
+            rules = setup.rules;
+            Some(session)
+        }
+        None => None,
+    };
 
     let title = env!("CARGO_PKG_NAME");
     let event_loop = EventLoop::new();
@@ -35,90 +99,66 @@ fn main() -> Result<()> {
     let window = WindowBuilder::new()
         .with_visible(false)
         .with_title(title)
+        .with_inner_size(PhysicalSize::new(settings.width, settings.height))
+        .with_fullscreen(if settings.fullscreen {
+            Some(Fullscreen::Borderless(monitor.clone()))
+        } else {
+            None
+        })
         .build(&event_loop)?;
 
-    let mut renderer = block_on(Renderer::new(&window, &video_mode))?;
-
-    let mut state = state::State {
-        ball: state::Ball {
-            position: (0.0, 0.0).into(),
-            velocity: (0.0, 0.0).into(),
-            radius: 0.05,
-            visible: true,
-        },
-        player1: state::Player {
-            position: (-0.8, 0.0).into(),
-            size: (0.05, 0.4).into(),
-            score: 0,
-            visible: true,
-        },
-        player2: state::Player {
-            position: (0.8, 0.0).into(),
-            size: (0.05, 0.4).into(),
-            score: 0,
-            visible: true,
-        },
-        title_text: state::Text {
-            position: (20.0, 20.0).into(),
-            color: (1.0, 1.0, 1.0, 1.0).into(),
-            text: String::from("PONG"),
-            size: 64.0,
-            ..Default::default()
-        },
-        play_button: state::Text {
-            position: (40.0, 100.0).into(),
-            color: (1.0, 1.0, 1.0, 1.0).into(),
-            text: String::from("Play"),
-            size: 32.0,
-            centered: false,
-            ..Default::default()
-        },
-        quit_button: state::Text {
-            position: (40.0, 160.0).into(),
-            color: (1.0, 1.0, 1.0, 1.0).into(),
-            text: String::from("Quit"),
-            size: 32.0,
-            ..Default::default()
-        },
-        player1_score: state::Text {
-            position: (renderer.width() * 0.25, 20.0).into(),
-            color: (1.0, 1.0, 1.0, 1.0).into(),
-            text: String::from("0"),
-            size: 32.0,
-            ..Default::default()
-        },
-        player2_score: state::Text {
-            position: (renderer.width() * 0.75, 20.0).into(),
-            color: (1.0, 1.0, 1.0, 1.0).into(),
-            text: String::from("0"),
-            size: 32.0,
-            ..Default::default()
-        },
-        win_text: state::Text {
-            position: (renderer.width() * 0.5, renderer.height() * 0.5).into(),
-            bounds: (renderer.width(), state::UNBOUNDED_F32).into(),
-            size: 32.0,
-            centered: true,
-            ..Default::default()
-        },
-        game_state: state::GameState::MainMenu,
-        prev_state: state::GameState::Quiting,
-    };
+    let mut renderer = block_on(Renderer::new(
+        &window,
+        &video_mode,
+        settings.vsync,
+        args.buffer_stats,
+    ))?;
+    if let Some(dir) = &args.skin {
+        if let Err(e) = renderer.load_skin(dir) {
+            log::warn!("Keeping the built-in sprites: {:?}", e);
+        }
+    }
+
+    log::info!("Match seed: {}", seed);
+    let mut state = state::State::new(renderer.width(), renderer.height(), seed);
+    state.difficulty = difficulty;
+    state.rules = rules.clone();
+    state.settings = settings;
+    state.rebinding_locked = replay.is_some() || args.record.is_some() || session.is_some();
+    state.saved_match = !state.rebinding_locked && has_saved_match(&args);
 
     let mut events = Vec::new();
     let mut input = Input::new();
+    let mut bindings = KeyBindings::load_or_default();
+
+    let mut simulation = Simulation::new(&mut state);
+    if session.is_some() {
+        simulation.start_match(&mut state);
+    }
+    bindings.update_labels(&mut state);
+    let audio_system = AudioSystem::with_default_device();
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = Gamepads::new();
+    #[cfg(feature = "hot-reload")]
+    let shader_watcher = ShaderWatcher::new()
+        .map_err(|e| log::error!("Shader hot-reload is off: {:?}", e))
+        .ok();
 
-    let mut menu_system = system::MenuSystem;
-    let mut serving_system = system::ServingSystem::new();
-    let mut play_system = system::PlaySystem;
-    let ball_system = system::BallSystem;
-    let mut game_over_system = system::GameOverSystem::new();
-    let base_render_system = system::BaseSystem;
+    let mut recording = args
+        .record
+        .as_ref()
+        .map(|_| Recording::new(seed, difficulty, rules));
+    let mut tick = 0;
 
-    let mut visiblity_system = system::VisibilitySystem;
-    visiblity_system.start(&mut state);
+    if let Some(dir) = &args.record_frames {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut frame = 0;
+    let mut screenshot_requested = false;
 
-    menu_system.start(&mut state);
+    let mut prev_state = state.clone();
+    let mut last_time = Instant::now();
+    let mut accumulator = 0.0;
 
     window.set_visible(true);
 
@@ -135,93 +175,170 @@ fn main() -> Result<()> {
                 window_id,
             } if window_id == window.id() => match event {
                 WindowEvent::CloseRequested => state.game_state = state::GameState::Quiting,
-                WindowEvent::KeyboardInput { input: w_input, .. } => match w_input {
-                    KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::Escape),
-                        ..
-                    } => {
-                        state.game_state = state::GameState::Quiting;
-                    }
-                    KeyboardInput {
-                        state: key_state,
-                        virtual_keycode: Some(key),
-                        ..
-                    } => {
-                        let input_handled = match state.game_state {
-                            state::GameState::Quiting => true,
-                            _ => {
-                                let handled = input.update(key, key_state);
-                                if key_state == &ElementState::Pressed {
-                                    base_render_system.update_state(
-                                        &input,
-                                        &mut state,
-                                        &mut events,
-                                    );
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: key_state,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } => {
+                    let pressed = *key_state == ElementState::Pressed;
+                    match (state.rebinding, bindings.action(*key)) {
+                        (Some(action), _) if pressed => {
+                            match bindings.rebind(action, *key) {
+                                Ok(()) => {
+                                    if let Err(e) = bindings.save() {
+                                        log::error!("Failed to save key bindings: {}", e);
+                                    }
                                 }
-                                handled
+                                Err(e) => log::warn!("Kept the old key bindings: {}", e),
                             }
-                        };
-                        if !input_handled {
-                            process_input(key_state, key, control_flow)
+                            bindings.update_labels(&mut state);
+                            state.rebinding = None;
+                        }
+                        (_, Some(Action::Quit)) if pressed => {
+                            state.game_state = state::GameState::Quiting;
                         }
+                        (_, Some(Action::Screenshot)) if pressed => screenshot_requested = true,
+                        (_, Some(action))
+                            if replay.is_none()
+                                && state.game_state != state::GameState::Quiting =>
+                        {
+                            input.apply(action, pressed);
+                        }
+                        _ => {}
                     }
-                    _ => {}
-                },
+                }
                 WindowEvent::Resized(physical_size) => {
                     renderer.resize(*physical_size);
+                    state.layout(renderer.width(), renderer.height());
                 }
                 WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                     renderer.resize(**new_inner_size);
+                    state.layout(renderer.width(), renderer.height());
                 }
                 _ => {}
             },
             Event::RedrawRequested(_) => {
-                visiblity_system.update_state(&input, &mut state, &mut events);
-                match state.game_state {
-                    state::GameState::MainMenu => {
-                        menu_system.update_state(&input, &mut state, &mut events);
-                        if state.game_state == state::GameState::Serving {
-                            serving_system.start(&mut state);
+                #[cfg(feature = "gamepad")]
+                if let (Some(gamepads), None) = (&mut gamepads, &replay) {
+                    gamepads.update(&mut input);
+                }
+
+                let now = Instant::now();
+                accumulator += (now - last_time).as_secs_f32().min(util::MAX_FRAME_TIME);
+                last_time = now;
+
+                while accumulator >= util::TIMESTEP {
+                    let dt = util::TIMESTEP;
+                    accumulator -= dt;
+                    prev_state = state.clone();
+
+                    if let Some(session) = &mut session {
+                        match session.advance(&mut simulation, &input, &mut state, &mut events, dt)
+                        {
+                            Ok(true) => tick += 1,
+                            // the other player is behind, wait for it
+                            Ok(false) => {}
+                            Err(e) => {
+                                log::error!("Network match over: {}", e);
+                                state.game_state = state::GameState::Quiting;
+                                break;
+                            }
                         }
+                        continue;
                     }
-                    state::GameState::Serving => {
-                        serving_system.update_state(&input, &mut state, &mut events);
-                        play_system.update_state(&input, &mut state, &mut events);
-                        if state.game_state == state::GameState::Playing {
-                            play_system.start(&mut state);
-                        }
+
+                    let tick_input = match &replay {
+                        Some(replay) => replay.inputs.get(tick).cloned().unwrap_or_default(),
+                        None => input.clone(),
+                    };
+                    if let Some(recording) = &mut recording {
+                        recording.push(&tick_input);
                     }
-                    state::GameState::Playing => {
-                        ball_system.update_state(&input, &mut state, &mut events);
-                        play_system.update_state(&input, &mut state, &mut events);
-                        if state.game_state == state::GameState::Serving {
-                            serving_system.start(&mut state);
-                        } else if state.game_state == state::GameState::GameOver {
-                            game_over_system.start(&mut state);
+                    simulation.tick(&tick_input, &mut state, &mut events, dt);
+                    tick += 1;
+
+                    if let Some(replay) = &replay {
+                        if tick == replay.inputs.len() {
+                            log::info!(
+                                "Replay finished with score {}:{}",
+                                state.player1.score,
+                                state.player2.score
+                            );
                         }
                     }
-                    state::GameState::GameOver => {
-                        game_over_system.update_state(&input, &mut state, &mut events);
-                        if state.game_state == state::GameState::MainMenu {
-                            menu_system.start(&mut state);
-                        }
+                }
+
+                if events
+                    .iter()
+                    .any(|event| matches!(event, state::Event::SaveAndQuit))
+                {
+                    if session.is_some() {
+                        log::warn!("Network matches can't be saved.");
+                    } else {
+                        save_match(&state);
                     }
-                    state::GameState::Quiting => {}
-                    state::GameState::Base => {
-                        // base_render_system.update_state(&input, &mut state, &mut events);
-                        use state::GameState::*;
-                        match state.game_state {
-                            MainMenu => menu_system.start(&mut state),
-                            Playing => play_system.start(&mut state),
-                            Serving => serving_system.start(&mut state),
-                            GameOver => game_over_system.start(&mut state),
-                            Quiting | state::GameState::Base => {}
+                }
+                if events
+                    .iter()
+                    .any(|event| matches!(event, state::Event::ResumeSavedMatch))
+                {
+                    resume_saved_match(&mut state, renderer.width(), renderer.height());
+                    bindings.update_labels(&mut state);
+                    prev_state = state.clone();
+                }
+
+                let changed = events
+                    .iter()
+                    .filter_map(|event| match event {
+                        state::Event::SettingsChanged(setting) => Some(*setting),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                // A replay goes through the Options menu of someone else's
+                // session, it must not resize the window or overwrite the
+                // settings of this one.
+                if !changed.is_empty() && replay.is_none() {
+                    apply_settings(&window, &mut renderer, &state.settings, &changed);
+                }
+
+                audio_system.update_state(&input, &mut state, &mut events, 0.0);
+
+                #[cfg(feature = "hot-reload")]
+                if let Some(watcher) = &shader_watcher {
+                    if watcher.changed() {
+                        renderer.reload_shaders(watcher.dir());
+                    }
+                }
+
+                let alpha = accumulator / util::TIMESTEP;
+
+                let mut captures = Vec::new();
+                if screenshot_requested || args.screenshot_at_frame == Some(frame) {
+                    screenshot_requested = false;
+                    let path = screenshot_path();
+                    log::info!("Saving a screenshot to {}", path.display());
+                    captures.push(path);
+                }
+                if let Some(dir) = &args.record_frames {
+                    captures.push(dir.join(format!("frame-{:06}.png", frame)));
+                }
+                if !captures.is_empty() {
+                    match renderer.capture_state(&prev_state, &state, alpha) {
+                        Ok(image) => {
+                            for path in &captures {
+                                save_capture(&image, path);
+                            }
                         }
+                        Err(e) => log::error!("Failed to capture frame {}: {}", frame, e),
                     }
                 }
+                frame += 1;
 
-                match renderer.render_state(&state) {
+                match renderer.render_state(&prev_state, &state, alpha) {
                     Ok(_) => {}
                     Err(e) => panic!("Panic requesting a renderer frame with an error:\n {}", e),
                 };
@@ -229,20 +346,207 @@ fn main() -> Result<()> {
                     window.request_redraw();
                 }
             }
+            Event::LoopDestroyed => {
+                if let Some(session) = &mut session {
+                    if let Err(e) = session.leave() {
+                        log::error!("Failed to say goodbye to the other player: {}", e);
+                    }
+                }
+                if let (Some(recording), Some(path)) = (&recording, &args.record) {
+                    match recording.save(path) {
+                        Ok(_) => log::info!("Saved {} ticks to {}", tick, path.display()),
+                        Err(e) => log::error!("Failed to save the recording: {}", e),
+                    }
+                }
+            }
             _ => {}
         }
     })
 }
 
-fn process_input(
-    element_state: &ElementState,
-    keycode: &VirtualKeyCode,
-    control_flow: &mut ControlFlow,
+/// Waits for or joins the other player of a network match, when asked
+/// to. The joining side plays with the host's seed and rules.
+fn connect(
+    args: &cli::Args,
+    seed: u64,
+    rules: &MatchRules,
+) -> Result<Option<(Session<UdpTransport>, MatchSetup)>> {
+    if (args.host.is_some() || args.join.is_some())
+        && (args.replay.is_some() || args.record.is_some())
+    {
+        bail!("Network matches can't be recorded or replayed.");
+    }
+    if let Some(addr) = args.host_addr() {
+        let setup = MatchSetup {
+            seed,
+            rules: rules.clone(),
+            input_delay: args.input_delay,
+        };
+        log::info!("Waiting for the other player on {}", addr);
+        let session = Session::host(UdpTransport::host(&addr)?, &setup, CONNECT_TIMEOUT)?;
+        return Ok(Some((session, setup)));
+    }
+    if let Some(addr) = args.join_addr() {
+        log::info!("Joining {}", addr);
+        let session = Session::join(UdpTransport::join(&addr)?, CONNECT_TIMEOUT)?;
+        return Ok(Some(session));
+    }
+    Ok(None)
+}
+
+/// Where the Options menu keeps the settings.
+fn settings_path() -> Option<PathBuf> {
+    config::path(settings::FILE_NAME)
+}
+
+/// Applies the settings the Options menu changed to the window and the
+/// renderer, and saves them.
+fn apply_settings(
+    window: &Window,
+    renderer: &mut Renderer,
+    settings: &Settings,
+    changed: &[Setting],
 ) {
-    match (keycode, element_state) {
-        (VirtualKeyCode::Escape, ElementState::Pressed) => {
-            *control_flow = ControlFlow::Exit;
+    for setting in changed {
+        match setting {
+            Setting::Fullscreen if settings.fullscreen => {
+                window.set_fullscreen(window.current_monitor().map(Fullscreen::Borderless))
+            }
+            Setting::Fullscreen => {
+                window.set_fullscreen(None);
+                window.set_inner_size(PhysicalSize::new(settings.width, settings.height));
+            }
+            Setting::Resolution => {
+                window.set_inner_size(PhysicalSize::new(settings.width, settings.height))
+            }
+            Setting::Vsync => renderer.set_vsync(settings.vsync),
+            // read every frame by the renderer, the audio and the systems
+            _ => {}
+        }
+    }
+    let path = match settings_path() {
+        Some(path) => path,
+        None => return log::error!("Failed to find the config directory."),
+    };
+    if let Err(e) = settings.save(&path) {
+        log::error!("Failed to save the settings: {}", e);
+    }
+}
+
+/// Where Save & Quit leaves the match for the next start.
+#[cfg(feature = "snapshot")]
+fn save_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("save.json"))
+}
+
+/// Saves the match paused, so it resumes on the pause menu.
+#[cfg(feature = "snapshot")]
+fn save_match(state: &state::State) {
+    let path = match save_path() {
+        Some(path) => path,
+        None => return log::error!("Failed to find the data directory."),
+    };
+    let mut saved = state.clone();
+    saved.game_state = state::GameState::Paused;
+    match snapshot::save(&saved, &path) {
+        Ok(_) => log::info!("Saved the match to {}", path.display()),
+        Err(e) => log::error!("Failed to save the match: {}", e),
+    }
+}
+
+#[cfg(not(feature = "snapshot"))]
+fn save_match(_state: &state::State) {
+    log::error!("Saving a match needs the snapshot feature.");
+}
+
+/// Whether the main menu offers to resume the match left with Save &
+/// Quit. A saved match brings its own seed and rules, so it isn't offered
+/// when either was given on the command line.
+#[cfg(feature = "snapshot")]
+fn has_saved_match(args: &cli::Args) -> bool {
+    let saved = save_path().map_or(false, |path| path.exists());
+    let flags = args.seed.is_some() || args.rules_file.is_some() || !args.rule_overrides.is_empty();
+    if saved && flags {
+        log::info!("Not offering the saved match, --seed or rule flags start a new one.");
+    }
+    saved && !flags
+}
+
+#[cfg(not(feature = "snapshot"))]
+fn has_saved_match(_args: &cli::Args) -> bool {
+    false
+}
+
+/// Picks up the match left with Save & Quit. The save is removed once the
+/// match is resumed, so it is resumed only once; a save that fails to load
+/// is kept and the Resume item turned off.
+#[cfg(feature = "snapshot")]
+fn resume_saved_match(state: &mut state::State, width: f32, height: f32) {
+    let path = match save_path() {
+        Some(path) => path,
+        None => return log::error!("Failed to find the data directory."),
+    };
+    match snapshot::load(&path) {
+        Ok(saved) => {
+            // the settings are not part of the save
+            let settings = std::mem::take(&mut state.settings);
+            let rebinding_locked = state.rebinding_locked;
+            *state = saved;
+            state.settings = settings;
+            state.rebinding_locked = rebinding_locked;
+            state.layout(width, height);
+            log::info!("Resumed the saved match, seed {}", state.seed);
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::error!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to resume the saved match: {:?}", e);
+            state.saved_match = false;
+            for menu in &mut state.menus {
+                menu.set_disabled(gnip_gnop::menu::MenuAction::ResumeSaved, true);
+            }
         }
-        _ => {}
     }
 }
+
+#[cfg(not(feature = "snapshot"))]
+fn resume_saved_match(_state: &mut state::State, _width: f32, _height: f32) {}
+
+/// Timestamped file name for a screenshot in the working directory.
+fn screenshot_path() -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    PathBuf::from(format!("{}-{}.png", env!("CARGO_PKG_NAME"), millis))
+}
+
+fn save_capture(image: &image::RgbaImage, path: &Path) {
+    match image.save(path) {
+        Ok(_) => log::debug!("Saved {}", path.display()),
+        Err(e) => log::error!("Failed to save {}: {}", path.display(), e),
+    }
+}
+
+/// Plays a replay without a window and checks the final score.
+fn run_headless(args: &cli::Args, replay: &Recording) -> Result<()> {
+    let state = replay.play(HEADLESS_WIDTH, HEADLESS_HEIGHT);
+    let score = (state.player1.score, state.player2.score);
+    println!(
+        "Replayed {} ticks, final score {}:{}",
+        replay.inputs.len(),
+        score.0,
+        score.1
+    );
+    if let Some(expected) = args.expect_score {
+        ensure!(
+            score == expected,
+            "Expected final score {}:{}, got {}:{}",
+            expected.0,
+            expected.1,
+            score.0,
+            score.1
+        );
+    }
+    Ok(())
+}
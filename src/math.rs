@@ -1,2 +1,35 @@
-pub(crate) type Vec2 = ultraviolet::Vec2;
-pub(crate) type Vec4 = ultraviolet::Vec4;
+pub type Vec2 = ultraviolet::Vec2;
+pub type Vec4 = ultraviolet::Vec4;
+
+/// Serde adapter storing a `Vec2` as `[x, y]`, for
+/// `#[serde(with = "crate::math::vec2")]`.
+#[cfg(feature = "snapshot")]
+pub mod vec2 {
+    use super::Vec2;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Vec2, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec2, D::Error> {
+        let [x, y] = <[f32; 2]>::deserialize(deserializer)?;
+        Ok(Vec2::new(x, y))
+    }
+}
+
+/// Serde adapter storing a `Vec4` as `[x, y, z, w]`.
+#[cfg(feature = "snapshot")]
+pub mod vec4 {
+    use super::Vec4;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Vec4, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y, v.z, v.w].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec4, D::Error> {
+        let [x, y, z, w] = <[f32; 4]>::deserialize(deserializer)?;
+        Ok(Vec4::new(x, y, z, w))
+    }
+}
@@ -0,0 +1,277 @@
+//! Menus declared as data: a title and an ordered list of items, each with
+//! a label and the action picking it triggers. Open menus are stacked in
+//! `State::menus`, so a submenu goes back to whatever opened it.
+
+#[cfg(feature = "snapshot")]
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Action, Input};
+use crate::math::{Vec2, Vec4};
+use crate::settings::Setting;
+use crate::state::{Event, State, Text, UNBOUNDED_F32};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum MenuId {
+    Main,
+    Options,
+    Controls,
+    Credits,
+    Pause,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum MenuAction {
+    OnePlayer,
+    TwoPlayers,
+    /// Picks up the match left with Save & Quit.
+    ResumeSaved,
+    /// Opens a submenu on top of the current one.
+    Open(MenuId),
+    /// Closes the current menu.
+    Back,
+    /// Waits for the next key and binds it to the action.
+    Rebind(Action),
+    /// Steps the setting to its next value.
+    Cycle(Setting),
+    Resume,
+    Restart,
+    MainMenu,
+    SaveAndQuit,
+    Quit,
+}
+
+const MAIN_ITEMS: &[(&str, Option<MenuAction>)] = &[
+    ("1 Player", Some(MenuAction::OnePlayer)),
+    ("2 Players", Some(MenuAction::TwoPlayers)),
+    ("Resume", Some(MenuAction::ResumeSaved)),
+    ("Options", Some(MenuAction::Open(MenuId::Options))),
+    ("Credits", Some(MenuAction::Open(MenuId::Credits))),
+    ("Quit", Some(MenuAction::Quit)),
+];
+
+const OPTIONS_ITEMS: &[(&str, Option<MenuAction>)] = &[
+    ("", Some(MenuAction::Cycle(Setting::Volume))),
+    ("", Some(MenuAction::Cycle(Setting::Fullscreen))),
+    ("", Some(MenuAction::Cycle(Setting::Vsync))),
+    ("", Some(MenuAction::Cycle(Setting::Resolution))),
+    ("", Some(MenuAction::Cycle(Setting::Theme))),
+    ("", Some(MenuAction::Cycle(Setting::PointsToWin))),
+    ("", Some(MenuAction::Cycle(Setting::WinByTwo))),
+    ("", Some(MenuAction::Cycle(Setting::Serve))),
+    ("", Some(MenuAction::Cycle(Setting::Spin))),
+    ("Controls", Some(MenuAction::Open(MenuId::Controls))),
+    ("Back", Some(MenuAction::Back)),
+];
+
+const CREDITS_ITEMS: &[(&str, Option<MenuAction>)] = &[
+    ("Made by Alex Komissarov", None),
+    ("Built with wgpu, winit, wgpu_glyph and rodio", None),
+    ("Back", Some(MenuAction::Back)),
+];
+
+const PAUSE_ITEMS: &[(&str, Option<MenuAction>)] = &[
+    ("Resume", Some(MenuAction::Resume)),
+    ("Restart", Some(MenuAction::Restart)),
+    ("Main Menu", Some(MenuAction::MainMenu)),
+    ("Save & Quit", Some(MenuAction::SaveAndQuit)),
+    ("Quit", Some(MenuAction::Quit)),
+];
+
+const ITEM_COLOR: (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 1.0);
+const DISABLED_COLOR: (f32, f32, f32, f32) = (0.4, 0.4, 0.4, 1.0);
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct MenuItem {
+    pub text: Text,
+    /// `None` for items that are only there to be read.
+    pub action: Option<MenuAction>,
+    /// Shown greyed out and skipped by the focus.
+    pub disabled: bool,
+}
+
+impl MenuItem {
+    fn selectable(&self) -> bool {
+        self.action.is_some() && !self.disabled
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Menu {
+    pub id: MenuId,
+    pub title: Text,
+    pub items: Vec<MenuItem>,
+    /// Index of the focused item.
+    pub focus: usize,
+    /// Centred over the match, like the pause menu, instead of filling the
+    /// screen from the top left.
+    pub overlay: bool,
+    /// Distance between two items in pixels.
+    pub spacing: f32,
+}
+
+impl Menu {
+    /// Builds the menu `id`, with labels showing the keys and settings of
+    /// `state`.
+    pub fn new(id: MenuId, state: &State) -> Self {
+        let (title, items, overlay, item_size, spacing) = match id {
+            MenuId::Main => ("PONG", items(MAIN_ITEMS), false, 32.0, 60.0),
+            MenuId::Options => ("OPTIONS", items(OPTIONS_ITEMS), false, 24.0, 40.0),
+            MenuId::Controls => {
+                let items = Action::ALL
+                    .iter()
+                    .map(|&action| (String::new(), Some(MenuAction::Rebind(action))))
+                    .chain(std::iter::once((
+                        String::from("Back"),
+                        Some(MenuAction::Back),
+                    )))
+                    .collect();
+                ("CONTROLS", items, false, 24.0, 40.0)
+            }
+            MenuId::Credits => ("CREDITS", items(CREDITS_ITEMS), false, 24.0, 40.0),
+            MenuId::Pause => ("PAUSED", items(PAUSE_ITEMS), true, 32.0, 48.0),
+        };
+
+        let mut menu = Self {
+            id,
+            title: Text {
+                color: ITEM_COLOR.into(),
+                text: String::from(title),
+                size: 64.0,
+                visible: true,
+                centered: overlay,
+                ..Default::default()
+            },
+            items: items
+                .into_iter()
+                .map(|(label, action)| MenuItem {
+                    text: Text {
+                        color: ITEM_COLOR.into(),
+                        text: label,
+                        size: item_size,
+                        visible: true,
+                        centered: overlay,
+                        ..Default::default()
+                    },
+                    action,
+                    disabled: false,
+                })
+                .collect(),
+            focus: 0,
+            overlay,
+            spacing,
+        };
+        menu.update_labels(state);
+        menu.set_disabled(MenuAction::SaveAndQuit, !cfg!(feature = "snapshot"));
+        menu.set_disabled(MenuAction::ResumeSaved, !state.saved_match);
+        for &action in Action::ALL.iter() {
+            menu.set_disabled(MenuAction::Rebind(action), state.rebinding_locked);
+        }
+        menu.set_focus(
+            menu.items
+                .iter()
+                .position(MenuItem::selectable)
+                .unwrap_or(0),
+        );
+        menu
+    }
+
+    pub fn layout(&mut self, width: f32, height: f32) {
+        let (title, first_item) = if self.overlay {
+            let title = Vec2::new(width * 0.5, height * 0.25);
+            (title, title + Vec2::new(0.0, 80.0))
+        } else {
+            (Vec2::new(20.0, 20.0), Vec2::new(40.0, 100.0))
+        };
+        let bounds = Vec2::new(width, UNBOUNDED_F32);
+        self.title.position = title;
+        self.title.bounds = bounds;
+        for (i, item) in self.items.iter_mut().enumerate() {
+            item.text.position = first_item + Vec2::new(0.0, i as f32 * self.spacing);
+            item.text.bounds = bounds;
+        }
+    }
+
+    /// Writes the keys of every action into the items that rebind them, and
+    /// the current value of every setting into the items that change it.
+    pub fn update_labels(&mut self, state: &State) {
+        for item in &mut self.items {
+            match item.action {
+                Some(MenuAction::Rebind(action)) => {
+                    let index = Action::ALL.iter().position(|&a| a == action);
+                    let keys = index
+                        .and_then(|index| state.key_labels.get(index))
+                        .map_or("-", String::as_str);
+                    item.text.text = format!("{}: {}", action.label(), keys);
+                }
+                Some(MenuAction::Cycle(setting)) => item.text.text = setting.label(state),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn set_disabled(&mut self, action: MenuAction, disabled: bool) {
+        for item in &mut self.items {
+            if item.action == Some(action) {
+                item.disabled = disabled;
+                item.text.color = Vec4::from(if disabled { DISABLED_COLOR } else { ITEM_COLOR });
+            }
+        }
+        if !self.items[self.focus].selectable() {
+            self.move_focus(1);
+        }
+    }
+
+    /// Moves the focus with the ui keys and returns the action of the
+    /// focused item when it was confirmed.
+    pub fn update(&mut self, input: &Input, events: &mut Vec<Event>) -> Option<MenuAction> {
+        let focus = self.focus;
+        if input.ui_down_pressed() {
+            self.move_focus(1);
+        } else if input.ui_up_pressed() {
+            self.move_focus(self.items.len() - 1);
+        }
+        if self.focus != focus {
+            events.push(Event::FocusChanged);
+        }
+
+        let item = &self.items[self.focus];
+        if input.enter_pressed && item.selectable() {
+            events.push(Event::ButtonPressed);
+            item.action
+        } else {
+            None
+        }
+    }
+
+    /// Steps the focus `step` items forward, wrapping around and skipping
+    /// items that can't be picked.
+    fn move_focus(&mut self, step: usize) {
+        let len = self.items.len();
+        let mut focus = self.focus;
+        for _ in 0..len {
+            focus = (focus + step) % len;
+            if self.items[focus].selectable() {
+                self.set_focus(focus);
+                return;
+            }
+        }
+    }
+
+    fn set_focus(&mut self, focus: usize) {
+        for (i, item) in self.items.iter_mut().enumerate() {
+            item.text.focused = i == focus;
+        }
+        self.focus = focus;
+    }
+}
+
+fn items(table: &[(&str, Option<MenuAction>)]) -> Vec<(String, Option<MenuAction>)> {
+    table
+        .iter()
+        .map(|&(label, action)| (String::from(label), action))
+        .collect()
+}
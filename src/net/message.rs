@@ -0,0 +1,226 @@
+//! Datagrams exchanged by two peers. Every message is a single UDP packet
+//! starting with a tag byte, all numbers are little endian:
+//!
+//! | tag | message  | content                                              |
+//! |-----|----------|------------------------------------------------------|
+//! | 0   | Hello    | u16 protocol version                                 |
+//! | 1   | Welcome  | u16 protocol version, u64 seed, u8 input delay,      |
+//! |     |          | u32 length + match rules as TOML                     |
+//! | 2   | Input    | u32 ack, u32 first tick, u8 count, 2 bytes per tick  |
+//! | 3   | Checksum | u32 tick, u64 checksum                               |
+//! | 4   | Bye      |                                                      |
+
+use eyre::*;
+use std::io::{Read, Write};
+
+use super::PeerInput;
+use crate::rules::MatchRules;
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// Sent by the joining peer until it is welcomed.
+    Hello { version: u16 },
+    /// The host's answer, with everything needed to start the same match.
+    Welcome {
+        version: u16,
+        seed: u64,
+        input_delay: u8,
+        rules: MatchRules,
+    },
+    /// Inputs of the sender for `first_tick` onward. `ack` is the number of
+    /// ticks of the receiver's input the sender already has.
+    Input {
+        ack: u32,
+        first_tick: u32,
+        inputs: Vec<PeerInput>,
+    },
+    /// Checksum of the sender's state after `tick`.
+    Checksum { tick: u32, checksum: u64 },
+    /// The sender left the match.
+    Bye,
+}
+
+impl Message {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match self {
+            Message::Hello { version } => {
+                bytes.write_all(&[0])?;
+                bytes.write_all(&version.to_le_bytes())?;
+            }
+            Message::Welcome {
+                version,
+                seed,
+                input_delay,
+                rules,
+            } => {
+                bytes.write_all(&[1])?;
+                bytes.write_all(&version.to_le_bytes())?;
+                bytes.write_all(&seed.to_le_bytes())?;
+                bytes.write_all(&[*input_delay])?;
+                let rules = rules.to_toml()?;
+                bytes.write_all(&(rules.len() as u32).to_le_bytes())?;
+                bytes.write_all(rules.as_bytes())?;
+            }
+            Message::Input {
+                ack,
+                first_tick,
+                inputs,
+            } => {
+                ensure!(
+                    inputs.len() <= u8::MAX as usize,
+                    "Too many inputs in one message."
+                );
+                bytes.write_all(&[2])?;
+                bytes.write_all(&ack.to_le_bytes())?;
+                bytes.write_all(&first_tick.to_le_bytes())?;
+                bytes.write_all(&[inputs.len() as u8])?;
+                for input in inputs {
+                    bytes.write_all(&input.to_bytes())?;
+                }
+            }
+            Message::Checksum { tick, checksum } => {
+                bytes.write_all(&[3])?;
+                bytes.write_all(&tick.to_le_bytes())?;
+                bytes.write_all(&checksum.to_le_bytes())?;
+            }
+            Message::Bye => bytes.write_all(&[4])?,
+        }
+        Ok(bytes)
+    }
+
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        let reader = &mut bytes;
+        let message = match read_u8(reader)? {
+            0 => Message::Hello {
+                version: read_u16(reader)?,
+            },
+            1 => {
+                let version = read_u16(reader)?;
+                let seed = read_u64(reader)?;
+                let input_delay = read_u8(reader)?;
+                let len = read_u32(reader)? as usize;
+                ensure!(len <= reader.len(), "Message is too short.");
+                let mut rules = vec![0; len];
+                read(reader, &mut rules)?;
+                Message::Welcome {
+                    version,
+                    seed,
+                    input_delay,
+                    rules: MatchRules::from_toml(std::str::from_utf8(&rules)?)?,
+                }
+            }
+            2 => {
+                let ack = read_u32(reader)?;
+                let first_tick = read_u32(reader)?;
+                let count = read_u8(reader)?;
+                let inputs = (0..count)
+                    .map(|_| {
+                        let mut bytes = [0; 2];
+                        read(reader, &mut bytes)?;
+                        Ok(PeerInput::from_bytes(bytes))
+                    })
+                    .collect::<Result<_>>()?;
+                Message::Input {
+                    ack,
+                    first_tick,
+                    inputs,
+                }
+            }
+            3 => Message::Checksum {
+                tick: read_u32(reader)?,
+                checksum: read_u64(reader)?,
+            },
+            4 => Message::Bye,
+            tag => bail!("Unknown message tag {}.", tag),
+        };
+        ensure!(reader.is_empty(), "Trailing bytes after a message.");
+        Ok(message)
+    }
+}
+
+fn read(reader: &mut &[u8], bytes: &mut [u8]) -> Result<()> {
+    reader.read_exact(bytes).wrap_err("Message is too short.")
+}
+
+fn read_u8(reader: &mut &[u8]) -> Result<u8> {
+    let mut bytes = [0; 1];
+    read(reader, &mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16(reader: &mut &[u8]) -> Result<u16> {
+    let mut bytes = [0; 2];
+    read(reader, &mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut bytes = [0; 4];
+    read(reader, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut &[u8]) -> Result<u64> {
+    let mut bytes = [0; 8];
+    read(reader, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn welcome() -> Message {
+        Message::Welcome {
+            version: PROTOCOL_VERSION,
+            seed: 42,
+            input_delay: 2,
+            rules: MatchRules::default(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages = vec![
+            Message::Hello {
+                version: PROTOCOL_VERSION,
+            },
+            welcome(),
+            Message::Input {
+                ack: 3,
+                first_tick: 1,
+                inputs: vec![
+                    PeerInput {
+                        up: true,
+                        axis: -127,
+                        ..PeerInput::default()
+                    },
+                    PeerInput {
+                        pause: true,
+                        ..PeerInput::default()
+                    },
+                ],
+            },
+            Message::Checksum {
+                tick: 29,
+                checksum: u64::MAX,
+            },
+            Message::Bye,
+        ];
+        for message in messages {
+            let bytes = message.encode().unwrap();
+            assert_eq!(Message::decode(&bytes).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn huge_rules_length_is_an_error() {
+        let mut bytes = welcome().encode().unwrap();
+        // The length follows tag, version, seed and input delay.
+        bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Message::decode(&bytes).is_err());
+    }
+}
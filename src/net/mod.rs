@@ -0,0 +1,648 @@
+//! Two player matches over the network.
+//!
+//! Both peers run the whole simulation and only exchange their own input
+//! per tick. When the input of the other peer for a tick hasn't arrived
+//! yet it is predicted to repeat the last one received. If the real input
+//! turns out different, the state is rolled back to that tick and the
+//! following ticks are simulated again. Delaying the local input by a few
+//! ticks gives the remote input time to arrive, so fewer rollbacks happen.
+
+pub mod message;
+pub mod transport;
+
+use eyre::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::input::Input;
+use crate::rules::MatchRules;
+use crate::sim::{Event, Side, Simulation, State};
+pub use message::{Message, PROTOCOL_VERSION};
+pub use transport::{FakeTransport, Transport, UdpTransport};
+
+pub const DEFAULT_PORT: u16 = 7878;
+pub const DEFAULT_INPUT_DELAY: u8 = 2;
+/// Ticks the simulation may run past the last confirmed remote input
+/// before it waits for the other peer.
+pub const MAX_PREDICTION: u32 = 8;
+/// Ticks between two compared state checksums.
+pub const CHECKSUM_INTERVAL: u32 = 30;
+/// The match is over when nothing arrives for this long.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+const HANDSHAKE_RESEND: Duration = Duration::from_millis(100);
+const HANDSHAKE_POLL: Duration = Duration::from_millis(10);
+/// Most inputs repeated in one `Message::Input`.
+const MAX_INPUTS_PER_MESSAGE: usize = 64;
+/// Own checksums kept around waiting for the remote ones.
+const MAX_PENDING_CHECKSUMS: usize = 64;
+
+/// The part of `Input` one peer contributes to a tick: its own paddle,
+/// whichever keys it is driven with, plus the menu keys.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PeerInput {
+    pub up: bool,
+    pub down: bool,
+    pub confirm: bool,
+    pub pause: bool,
+    pub axis: i8,
+}
+
+impl PeerInput {
+    pub fn from_local(input: &Input) -> Self {
+        Self {
+            up: input.p1_up_pressed || input.p2_up_pressed,
+            down: input.p1_down_pressed || input.p2_down_pressed,
+            confirm: input.enter_pressed,
+            pause: input.space_pressed,
+            axis: if input.p1_axis != 0 {
+                input.p1_axis
+            } else {
+                input.p2_axis
+            },
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 2] {
+        let flags = self.up as u8
+            | (self.down as u8) << 1
+            | (self.confirm as u8) << 2
+            | (self.pause as u8) << 3;
+        let [axis] = self.axis.to_le_bytes();
+        [flags, axis]
+    }
+
+    pub fn from_bytes(bytes: [u8; 2]) -> Self {
+        Self {
+            up: bytes[0] & 1 != 0,
+            down: bytes[0] & 1 << 1 != 0,
+            confirm: bytes[0] & 1 << 2 != 0,
+            pause: bytes[0] & 1 << 3 != 0,
+            axis: i8::from_le_bytes([bytes[1]]),
+        }
+    }
+}
+
+/// Builds the input of a tick from the left and the right peer.
+pub fn combine(left: PeerInput, right: PeerInput) -> Input {
+    Input {
+        p1_up_pressed: left.up,
+        p1_down_pressed: left.down,
+        p2_up_pressed: right.up,
+        p2_down_pressed: right.down,
+        enter_pressed: left.confirm || right.confirm,
+        space_pressed: left.pause || right.pause,
+        p1_axis: left.axis,
+        p2_axis: right.axis,
+    }
+}
+
+/// What both peers need to agree on before the first tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchSetup {
+    pub seed: u64,
+    pub rules: MatchRules,
+    pub input_delay: u8,
+}
+
+pub struct Session<T: Transport> {
+    transport: T,
+    side: Side,
+    /// Packet the host answers every `Hello` with, in case its first
+    /// answer got lost.
+    welcome: Option<Vec<u8>>,
+    /// Next tick to simulate.
+    tick: u32,
+    /// Own input per tick, `input_delay` ticks ahead of `tick`.
+    local_inputs: Vec<PeerInput>,
+    /// Input of the other peer for every tick received so far.
+    remote_inputs: Vec<PeerInput>,
+    /// Remote input every simulated tick was run with, known or predicted.
+    used_remote_inputs: Vec<PeerInput>,
+    /// Ticks of own input the other peer has.
+    remote_ack: u32,
+    /// State before each tick that may still be rolled back, oldest first.
+    snapshots: VecDeque<(u32, State)>,
+    /// Next tick that gets a checksum once it is confirmed.
+    next_checksum: u32,
+    local_checksums: HashMap<u32, u64>,
+    remote_checksums: HashMap<u32, u64>,
+    last_received: Instant,
+}
+
+impl<T: Transport> Session<T> {
+    /// Waits up to `timeout` for a peer to join and welcomes it with
+    /// `setup`. The host plays the left paddle.
+    pub fn host(mut transport: T, setup: &MatchSetup, timeout: Duration) -> Result<Self> {
+        let welcome = Message::Welcome {
+            version: PROTOCOL_VERSION,
+            seed: setup.seed,
+            input_delay: setup.input_delay,
+            rules: setup.rules.clone(),
+        }
+        .encode()?;
+
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            while let Some(packet) = transport.recv()? {
+                match Message::decode(&packet) {
+                    // A stray or outdated client must not end the hosting.
+                    Ok(Message::Hello { version }) if version != PROTOCOL_VERSION => {
+                        log::warn!(
+                            "Ignoring a player speaking protocol version {}, expected {}.",
+                            version,
+                            PROTOCOL_VERSION
+                        )
+                    }
+                    Ok(Message::Hello { .. }) => {
+                        transport.send(&welcome)?;
+                        let mut session = Self::new(transport, Side::Left, setup.input_delay);
+                        session.welcome = Some(welcome);
+                        return Ok(session);
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Dropping a malformed packet: {}", e),
+                }
+            }
+            std::thread::sleep(HANDSHAKE_POLL);
+        }
+        bail!("Nobody joined within {} seconds.", timeout.as_secs())
+    }
+
+    /// Says hello to the host until it answers, for up to `timeout`. The
+    /// joining peer plays the right paddle.
+    pub fn join(mut transport: T, timeout: Duration) -> Result<(Self, MatchSetup)> {
+        let hello = Message::Hello {
+            version: PROTOCOL_VERSION,
+        }
+        .encode()?;
+
+        let start = Instant::now();
+        let mut next_hello = start;
+        while start.elapsed() < timeout {
+            if Instant::now() >= next_hello {
+                transport.send(&hello)?;
+                next_hello += HANDSHAKE_RESEND;
+            }
+            while let Some(packet) = transport.recv()? {
+                match Message::decode(&packet) {
+                    Ok(Message::Welcome {
+                        version,
+                        seed,
+                        input_delay,
+                        rules,
+                    }) => {
+                        ensure!(
+                            version == PROTOCOL_VERSION,
+                            "The host speaks protocol version {}, expected {}.",
+                            version,
+                            PROTOCOL_VERSION
+                        );
+                        let setup = MatchSetup {
+                            seed,
+                            rules,
+                            input_delay,
+                        };
+                        return Ok((Self::new(transport, Side::Right, input_delay), setup));
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Dropping a malformed packet: {}", e),
+                }
+            }
+            std::thread::sleep(HANDSHAKE_POLL);
+        }
+        bail!(
+            "The host did not answer within {} seconds.",
+            timeout.as_secs()
+        )
+    }
+
+    fn new(transport: T, side: Side, input_delay: u8) -> Self {
+        Self {
+            transport,
+            side,
+            welcome: None,
+            tick: 0,
+            local_inputs: vec![PeerInput::default(); input_delay as usize],
+            remote_inputs: Vec::new(),
+            used_remote_inputs: Vec::new(),
+            remote_ack: 0,
+            snapshots: VecDeque::new(),
+            next_checksum: CHECKSUM_INTERVAL - 1,
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            last_received: Instant::now(),
+        }
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// Number of ticks simulated so far.
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// Runs one tick with `input` as the local player's input, rolling back
+    /// first if remote inputs arrived that differ from the predicted ones.
+    ///
+    /// Returns `false` without simulating when the other peer is too far
+    /// behind, and an error when the peers desynced or the other one left.
+    pub fn advance(
+        &mut self,
+        simulation: &mut Simulation,
+        input: &Input,
+        state: &mut State,
+        events: &mut Vec<Event>,
+        dt: f32,
+    ) -> Result<bool> {
+        let rollback = self.receive()?;
+        if let Some(tick) = rollback {
+            self.rollback(tick, simulation, state, dt);
+        }
+
+        let advanced = self.tick < self.remote_inputs.len() as u32 + MAX_PREDICTION;
+        if advanced {
+            self.local_inputs.push(PeerInput::from_local(input));
+            self.snapshots.push_back((self.tick, state.clone()));
+            self.simulate_tick(simulation, state, events, dt);
+        }
+
+        self.exchange_checksums(state)?;
+        let confirmed = self.confirmed();
+        self.snapshots.retain(|(tick, _)| *tick >= confirmed);
+
+        self.send_inputs()?;
+        Ok(advanced)
+    }
+
+    /// Tells the other peer this one is leaving.
+    pub fn leave(&mut self) -> Result<()> {
+        self.transport.send(&Message::Bye.encode()?)
+    }
+
+    /// Last tick whose state is final on this peer, plus one.
+    fn confirmed(&self) -> u32 {
+        self.tick.min(self.remote_inputs.len() as u32)
+    }
+
+    /// Handles every pending packet and returns the first tick that was
+    /// simulated with a wrong prediction.
+    fn receive(&mut self) -> Result<Option<u32>> {
+        let mut rollback = None;
+        while let Some(packet) = self.transport.recv()? {
+            let message = match Message::decode(&packet) {
+                Ok(message) => message,
+                Err(e) => {
+                    log::warn!("Dropping a malformed packet: {}", e);
+                    continue;
+                }
+            };
+            self.last_received = Instant::now();
+            match message {
+                Message::Hello { .. } => {
+                    if let Some(welcome) = &self.welcome {
+                        self.transport.send(welcome)?;
+                    }
+                }
+                Message::Welcome { .. } => {}
+                Message::Input {
+                    ack,
+                    first_tick,
+                    inputs,
+                } => {
+                    self.remote_ack = self.remote_ack.max(ack);
+                    let known = self.remote_inputs.len() as u32;
+                    if first_tick > known {
+                        // A gap, an older packet still has to come.
+                        continue;
+                    }
+                    for input in inputs.into_iter().skip((known - first_tick) as usize) {
+                        let tick = self.remote_inputs.len();
+                        if tick < self.used_remote_inputs.len()
+                            && self.used_remote_inputs[tick] != input
+                            && rollback.is_none()
+                        {
+                            rollback = Some(tick as u32);
+                        }
+                        self.remote_inputs.push(input);
+                    }
+                }
+                Message::Checksum { tick, checksum } => {
+                    self.remote_checksums.insert(tick, checksum);
+                }
+                Message::Bye => bail!("The other player left."),
+            }
+        }
+        ensure!(
+            self.last_received.elapsed() < TIMEOUT,
+            "Lost the connection to the other player."
+        );
+        Ok(rollback)
+    }
+
+    /// Restores the state before `tick` and simulates up to the current
+    /// tick again with the inputs known now. The window side of the state,
+    /// like its layout and the settings, stays as it is now.
+    fn rollback(&mut self, tick: u32, simulation: &mut Simulation, state: &mut State, dt: f32) {
+        let position = match self.snapshots.iter().position(|(t, _)| *t == tick) {
+            Some(position) => position,
+            None => {
+                log::error!("No snapshot to roll back to tick {}", tick);
+                return;
+            }
+        };
+        let last_tick = self.tick;
+        let current = std::mem::replace(state, self.snapshots[position].1.clone());
+        self.snapshots.truncate(position);
+        self.used_remote_inputs.truncate(tick as usize);
+        self.tick = tick;
+
+        // Sounds of the replayed ticks already played the first time round.
+        let mut events = Vec::new();
+        while self.tick < last_tick {
+            self.snapshots.push_back((self.tick, state.clone()));
+            self.simulate_tick(simulation, state, &mut events, dt);
+            events.clear();
+        }
+        // The replayed ticks ran with the old settings, their changes were
+        // dropped with the events.
+        state.keep_window_state(&current);
+    }
+
+    fn simulate_tick(
+        &mut self,
+        simulation: &mut Simulation,
+        state: &mut State,
+        events: &mut Vec<Event>,
+        dt: f32,
+    ) {
+        let tick = self.tick as usize;
+        let local = self.local_inputs[tick];
+        let remote = match self.remote_inputs.get(tick) {
+            Some(&remote) => remote,
+            None => self.remote_inputs.last().copied().unwrap_or_default(),
+        };
+        let input = match self.side {
+            Side::Left => combine(local, remote),
+            Side::Right => combine(remote, local),
+        };
+        simulation.tick(&input, state, events, dt);
+        self.used_remote_inputs.push(remote);
+        self.tick += 1;
+    }
+
+    /// Checksums every newly confirmed checksum tick and compares it with
+    /// the other peer's.
+    fn exchange_checksums(&mut self, state: &State) -> Result<()> {
+        while self.next_checksum < self.confirmed() {
+            let tick = self.next_checksum;
+            // state after `tick` is the one before the following tick
+            let checksum = if tick + 1 == self.tick {
+                state.checksum()
+            } else {
+                match self.snapshots.iter().find(|(t, _)| *t == tick + 1) {
+                    Some((_, snapshot)) => snapshot.checksum(),
+                    None => break,
+                }
+            };
+            self.transport
+                .send(&Message::Checksum { tick, checksum }.encode()?)?;
+            self.local_checksums.insert(tick, checksum);
+            self.next_checksum += CHECKSUM_INTERVAL;
+        }
+
+        let compared = self
+            .local_checksums
+            .iter()
+            .filter_map(|(tick, local)| {
+                self.remote_checksums
+                    .get(tick)
+                    .map(|remote| (*tick, *local, *remote))
+            })
+            .collect::<Vec<_>>();
+        for (tick, local, remote) in compared {
+            ensure!(
+                local == remote,
+                "Desync at tick {}: checksum {:016x}, the other player has {:016x}.",
+                tick,
+                local,
+                remote
+            );
+            self.local_checksums.remove(&tick);
+            self.remote_checksums.remove(&tick);
+        }
+        if self.local_checksums.len() > MAX_PENDING_CHECKSUMS {
+            let oldest = self.next_checksum - CHECKSUM_INTERVAL * MAX_PENDING_CHECKSUMS as u32;
+            self.local_checksums.retain(|tick, _| *tick >= oldest);
+        }
+        Ok(())
+    }
+
+    /// Sends every own input the other peer hasn't acknowledged yet.
+    fn send_inputs(&mut self) -> Result<()> {
+        let first_tick = self.remote_ack;
+        let inputs = self
+            .local_inputs
+            .iter()
+            .skip(first_tick as usize)
+            .take(MAX_INPUTS_PER_MESSAGE)
+            .copied()
+            .collect();
+        let message = Message::Input {
+            ack: self.remote_inputs.len() as u32,
+            first_tick,
+            inputs,
+        };
+        self.transport.send(&message.encode()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec2;
+    use crate::util::TIMESTEP;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const TICKS: u32 = 600;
+    const DELAY: u8 = 2;
+
+    /// Input a peer gives at `tick`, different on both sides so that the
+    /// predictions are often wrong.
+    fn script(side: Side, tick: u32) -> Input {
+        let offset = match side {
+            Side::Left => 0,
+            Side::Right => 3,
+        };
+        let up = (tick / 7 + offset) % 3 == 0;
+        let down = !up && (tick / 11 + offset) % 4 == 0;
+        match side {
+            Side::Left => Input {
+                p1_up_pressed: up,
+                p1_down_pressed: down,
+                ..Input::default()
+            },
+            Side::Right => Input {
+                p2_up_pressed: up,
+                p2_down_pressed: down,
+                ..Input::default()
+            },
+        }
+    }
+
+    /// Plays `TICKS` ticks and then keeps exchanging inputs until both
+    /// peers know every input, returning the checksum of the final state.
+    fn play(
+        mut session: Session<FakeTransport>,
+        setup: &MatchSetup,
+        settled: &AtomicUsize,
+    ) -> Result<u64> {
+        let mut state = State::new(800.0, 600.0, setup.seed);
+        state.rules = setup.rules.clone();
+        let mut simulation = Simulation::new(&mut state);
+        simulation.start_match(&mut state);
+        let mut events = Vec::new();
+
+        while session.tick() < TICKS {
+            let input = script(session.side(), session.tick());
+            if !session.advance(&mut simulation, &input, &mut state, &mut events, TIMESTEP)? {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            events.clear();
+        }
+
+        let mut confirmed = false;
+        while !confirmed || settled.load(Ordering::SeqCst) < 2 {
+            if let Some(tick) = session.receive()? {
+                session.rollback(tick, &mut simulation, &mut state, TIMESTEP);
+            }
+            session.send_inputs()?;
+            if !confirmed && session.confirmed() == TICKS {
+                confirmed = true;
+                settled.fetch_add(1, Ordering::SeqCst);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Ok(state.checksum())
+    }
+
+    /// The match both peers should have played, run without a network.
+    fn expected_checksum(setup: &MatchSetup) -> u64 {
+        let mut state = State::new(800.0, 600.0, setup.seed);
+        state.rules = setup.rules.clone();
+        let mut simulation = Simulation::new(&mut state);
+        simulation.start_match(&mut state);
+        let mut events = Vec::new();
+        let delayed = |side, tick: u32| match tick.checked_sub(DELAY as u32) {
+            Some(tick) => PeerInput::from_local(&script(side, tick)),
+            None => PeerInput::default(),
+        };
+        for tick in 0..TICKS {
+            let input = combine(delayed(Side::Left, tick), delayed(Side::Right, tick));
+            simulation.tick(&input, &mut state, &mut events, TIMESTEP);
+        }
+        state.checksum()
+    }
+
+    #[test]
+    fn rollback_keeps_resizes_and_settings() {
+        let (transport, mut remote) = FakeTransport::pair(0.0, 0.0, 1);
+        let mut session = Session::new(transport, Side::Left, DELAY);
+        let mut state = State::new(800.0, 600.0, 1);
+        let mut simulation = Simulation::new(&mut state);
+        simulation.start_match(&mut state);
+        let mut events = Vec::new();
+        for _ in 0..5 {
+            assert!(session
+                .advance(
+                    &mut simulation,
+                    &Input::default(),
+                    &mut state,
+                    &mut events,
+                    TIMESTEP
+                )
+                .unwrap());
+        }
+
+        state.layout(1024.0, 768.0);
+        state.settings.vsync = !state.settings.vsync;
+        let settings = state.settings.clone();
+
+        // The right paddle moved from the start, not idle as predicted.
+        let up = PeerInput {
+            up: true,
+            ..PeerInput::default()
+        };
+        let message = Message::Input {
+            ack: 0,
+            first_tick: 0,
+            inputs: vec![up; 5],
+        };
+        remote.send(&message.encode().unwrap()).unwrap();
+        let before = state.player2.position;
+        session
+            .advance(
+                &mut simulation,
+                &Input::default(),
+                &mut state,
+                &mut events,
+                TIMESTEP,
+            )
+            .unwrap();
+
+        assert!(state.player2.position.y > before.y);
+        assert_eq!(state.screen_size, Vec2::new(1024.0, 768.0));
+        assert_eq!(state.player2_score.position, Vec2::new(768.0, 20.0));
+        assert_eq!(state.win_text.position, Vec2::new(512.0, 384.0));
+        assert_eq!(state.settings, settings);
+    }
+
+    #[test]
+    fn host_keeps_waiting_after_a_hello_of_another_version() {
+        let (transport, mut remote) = FakeTransport::pair(0.0, 0.0, 1);
+        for &version in &[PROTOCOL_VERSION + 1, PROTOCOL_VERSION] {
+            remote
+                .send(&Message::Hello { version }.encode().unwrap())
+                .unwrap();
+        }
+        let setup = MatchSetup {
+            seed: 42,
+            rules: MatchRules::default(),
+            input_delay: DELAY,
+        };
+        Session::host(transport, &setup, TIMEOUT).unwrap();
+        let welcome = Message::decode(&remote.recv().unwrap().unwrap()).unwrap();
+        assert!(matches!(welcome, Message::Welcome { seed: 42, .. }));
+        assert!(remote.recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn peers_agree_over_a_lossy_connection() {
+        let (host_transport, join_transport) = FakeTransport::pair(0.2, 0.2, 7);
+        let setup = MatchSetup {
+            seed: 42,
+            rules: MatchRules::default(),
+            input_delay: DELAY,
+        };
+        let settled = Arc::new(AtomicUsize::new(0));
+
+        let host = {
+            let setup = setup.clone();
+            let settled = settled.clone();
+            std::thread::spawn(move || {
+                let session = Session::host(host_transport, &setup, TIMEOUT)?;
+                play(session, &setup, &settled)
+            })
+        };
+        let (session, joined) = Session::join(join_transport, TIMEOUT).unwrap();
+        assert_eq!(joined, setup);
+        let join_checksum = play(session, &joined, &settled).unwrap();
+        let host_checksum = host.join().unwrap().unwrap();
+
+        assert_eq!(host_checksum, join_checksum);
+        assert_eq!(host_checksum, expected_checksum(&setup));
+    }
+}
@@ -0,0 +1,157 @@
+//! Ways to move datagrams between the two peers.
+
+use eyre::*;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use super::{Message, PROTOCOL_VERSION};
+use crate::rng::Rng;
+
+/// Largest datagram either peer sends.
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+/// Unreliable, unordered delivery of whole packets. Neither call blocks.
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]) -> Result<()>;
+    /// Returns the next received packet, `None` when there is none yet.
+    fn recv(&mut self) -> Result<Option<Vec<u8>>>;
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    /// The other peer. A host learns it from the first `Hello` it gets.
+    peer: Option<SocketAddr>,
+}
+
+impl UdpTransport {
+    /// Waits for a peer on `addr`, e.g. `0.0.0.0:7878`.
+    pub fn host(addr: impl ToSocketAddrs) -> Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, peer: None })
+    }
+
+    /// Talks to the host at `addr`.
+    pub fn join(addr: impl ToSocketAddrs) -> Result<Self> {
+        let peer = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| eyre!("The host address resolves to nothing."))?;
+        let local: SocketAddr = if peer.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            peer: Some(peer),
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        if let Some(peer) = self.peer {
+            match self.socket.send_to(packet, peer) {
+                Ok(_) => {}
+                // The peer isn't listening (yet), the packet is simply lost.
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, from)) => {
+                    let packet = &buffer[..len];
+                    match self.peer {
+                        Some(peer) if peer != from => {
+                            log::warn!("Ignoring a packet from {}", from);
+                        }
+                        Some(_) => return Ok(Some(packet.to_vec())),
+                        // Anyone can send to the port, so only a peer saying
+                        // hello in this protocol version becomes the other
+                        // player.
+                        None => match Message::decode(packet) {
+                            Ok(Message::Hello { version }) if version == PROTOCOL_VERSION => {
+                                self.peer = Some(from);
+                                return Ok(Some(packet.to_vec()));
+                            }
+                            _ => log::warn!("Ignoring a packet from {} before its hello", from),
+                        },
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// In-process transport that loses and reorders packets, for exercising
+/// the session without a network. The two ends may live on different
+/// threads, so both can wait in their handshake at the same time.
+pub struct FakeTransport {
+    inbox: Queue,
+    outbox: Queue,
+    /// Chance of a sent packet being dropped.
+    loss: f32,
+    /// Chance of a sent packet overtaking the one sent before it.
+    reorder: f32,
+    rng: Rng,
+}
+
+impl FakeTransport {
+    /// Creates two connected ends.
+    pub fn pair(loss: f32, reorder: f32, seed: u64) -> (Self, Self) {
+        let a = Queue::default();
+        let b = Queue::default();
+        (
+            Self {
+                inbox: a.clone(),
+                outbox: b.clone(),
+                loss,
+                reorder,
+                rng: Rng::new(seed),
+            },
+            Self {
+                inbox: b,
+                outbox: a,
+                loss,
+                reorder,
+                rng: Rng::new(seed.wrapping_add(1)),
+            },
+        )
+    }
+}
+
+impl Transport for FakeTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        if self.rng.next_f32() < self.loss {
+            return Ok(());
+        }
+        let mut outbox = self.outbox.lock().unwrap();
+        if !outbox.is_empty() && self.rng.next_f32() < self.reorder {
+            let last = outbox.len() - 1;
+            outbox.insert(last, packet.to_vec());
+        } else {
+            outbox.push_back(packet.to_vec());
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.inbox.lock().unwrap().pop_front())
+    }
+}
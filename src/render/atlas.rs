@@ -0,0 +1,263 @@
+//! Packs the sprite PNGs of a skin into a single texture, so every entity
+//! is drawn with the same bind group.
+
+use eyre::*;
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::math::Vec4;
+
+/// Transparent pixels kept around every sprite, so the sampler never picks
+/// up a neighbour.
+const PADDING: u32 = 1;
+/// Largest atlas side every adapter supports.
+const MAX_SIZE: u32 = 4096;
+
+/// Sprites drawn without `--skin`, built in like the font. They are white,
+/// so they take the colours of the theme.
+const DEFAULT_SPRITES: &[(&str, &[u8])] = &[
+    ("ball", include_bytes!("../../res/sprites/ball.png")),
+    ("paddle", include_bytes!("../../res/sprites/paddle.png")),
+];
+
+/// How one entity is drawn: the region of the atlas it shows and the
+/// colour that region is multiplied with.
+#[derive(Debug, Copy, Clone)]
+pub struct Sprite {
+    pub uv_rect: Vec4,
+    pub color: Vec4,
+}
+
+pub struct Atlas {
+    image: RgbaImage,
+    /// Region of every sprite in texture coordinates: top left corner in
+    /// `x` and `y`, size in `z` and `w`.
+    regions: HashMap<String, Vec4>,
+    /// A single white pixel, so untextured quads keep their plain colour.
+    white: Vec4,
+    /// Sprites are multiplied with the colour they are drawn with, like the
+    /// white default ones, instead of showing as they are.
+    tinted: bool,
+}
+
+impl Atlas {
+    /// Packs the built-in sprites.
+    pub fn default_skin() -> Result<Self> {
+        let sprites = DEFAULT_SPRITES
+            .iter()
+            .map(|&(name, bytes)| {
+                let image = image::load_from_memory(bytes)
+                    .wrap_err_with(|| format!("Failed to load the built-in sprite {}", name))?
+                    .into_rgba8();
+                Ok((String::from(name), image))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            tinted: true,
+            ..Self::pack(sprites)?
+        })
+    }
+
+    /// Packs every PNG in `dir`, named after its file without `.png`.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut sprites = Vec::new();
+        let entries = std::fs::read_dir(dir)
+            .wrap_err_with(|| format!("Failed to read the sprites in {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("png") {
+                let name = match path.file_stem() {
+                    Some(stem) => stem.to_string_lossy().into_owned(),
+                    None => continue,
+                };
+                let image = image::open(&path)
+                    .wrap_err_with(|| format!("Failed to load sprite {}", path.display()))?
+                    .into_rgba8();
+                sprites.push((name, image));
+            }
+        }
+        Self::pack(sprites)
+    }
+
+    /// Places the sprites in rows, tallest first, after the white pixel.
+    pub fn pack(mut sprites: Vec<(String, RgbaImage)>) -> Result<Self> {
+        sprites.sort_by(|(a_name, a), (b_name, b)| {
+            b.height().cmp(&a.height()).then_with(|| a_name.cmp(b_name))
+        });
+        let sizes: Vec<(u32, u32)> = std::iter::once((1, 1))
+            .chain(sprites.iter().map(|(_, sprite)| sprite.dimensions()))
+            .map(|(width, height)| (width + 2 * PADDING, height + 2 * PADDING))
+            .collect();
+        let area: u32 = sizes.iter().map(|(width, height)| width * height).sum();
+        let widest = sizes.iter().map(|(width, _)| *width).max().unwrap_or(0);
+        let width = ((area as f32).sqrt().ceil() as u32)
+            .max(widest)
+            .next_power_of_two();
+
+        let mut corners = Vec::with_capacity(sizes.len());
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for (slot_width, slot_height) in sizes {
+            if x + slot_width > width {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            corners.push((x + PADDING, y + PADDING));
+            x += slot_width;
+            row_height = row_height.max(slot_height);
+        }
+        let height = (y + row_height).next_power_of_two();
+        ensure!(
+            width <= MAX_SIZE && height <= MAX_SIZE,
+            "The sprites need a {}x{} atlas, larger than the supported {}x{}.",
+            width,
+            height,
+            MAX_SIZE,
+            MAX_SIZE
+        );
+
+        let mut image = RgbaImage::new(width, height);
+        let (white_x, white_y) = corners[0];
+        image.put_pixel(white_x, white_y, Rgba([255; 4]));
+        // The centre of the pixel, any size would sample its edges.
+        let white = Vec4::new(
+            (white_x as f32 + 0.5) / width as f32,
+            (white_y as f32 + 0.5) / height as f32,
+            0.0,
+            0.0,
+        );
+
+        let mut regions = HashMap::with_capacity(sprites.len());
+        for ((name, sprite), &(x, y)) in sprites.into_iter().zip(&corners[1..]) {
+            image::imageops::replace(&mut image, &sprite, x, y);
+            let uv_rect = Vec4::new(
+                x as f32 / width as f32,
+                y as f32 / height as f32,
+                sprite.width() as f32 / width as f32,
+                sprite.height() as f32 / height as f32,
+            );
+            regions.insert(name, uv_rect);
+        }
+        Ok(Self {
+            image,
+            regions,
+            white,
+            tinted: false,
+        })
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// The sprite called `name` as drawn from its image, or a plain quad of
+    /// `color` when the skin has none.
+    pub fn sprite(&self, name: &str, color: Vec4) -> Sprite {
+        match self.regions.get(name) {
+            Some(&uv_rect) => Sprite {
+                uv_rect,
+                color: if self.tinted { color } else { Vec4::one() },
+            },
+            None => Sprite {
+                uv_rect: self.white,
+                color,
+            },
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.regions.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(width: u32, height: u32, value: u8) -> (String, RgbaImage) {
+        (
+            format!("sprite{}", value),
+            RgbaImage::from_pixel(width, height, Rgba([value; 4])),
+        )
+    }
+
+    /// Pixel bounds `(x, y, width, height)` of the region `uv_rect` covers.
+    fn pixel_rect(atlas: &Atlas, uv_rect: Vec4) -> (u32, u32, u32, u32) {
+        let (width, height) = atlas.image().dimensions();
+        let (width, height) = (width as f32, height as f32);
+        (
+            (uv_rect.x * width).round() as u32,
+            (uv_rect.y * height).round() as u32,
+            (uv_rect.z * width).round() as u32,
+            (uv_rect.w * height).round() as u32,
+        )
+    }
+
+    #[test]
+    fn pack_copies_every_sprite_into_its_own_padded_region() {
+        let sprites = vec![sprite(8, 8, 10), sprite(4, 32, 20), sprite(16, 12, 30)];
+        let atlas = Atlas::pack(sprites.clone()).unwrap();
+        let image = atlas.image();
+        assert!(image.width().is_power_of_two() && image.height().is_power_of_two());
+        assert_eq!(atlas.len(), sprites.len());
+
+        for (name, sprite) in &sprites {
+            let region = atlas.sprite(name, Vec4::zero()).uv_rect;
+            let (x, y, width, height) = pixel_rect(&atlas, region);
+            assert_eq!((width, height), sprite.dimensions(), "{}", name);
+            let value = sprite.get_pixel(0, 0).0;
+            // The sprite itself, then the transparent padding around it.
+            for py in y - PADDING..y + height + PADDING {
+                for px in x - PADDING..x + width + PADDING {
+                    let inside = (x..x + width).contains(&px) && (y..y + height).contains(&py);
+                    let expected = if inside { value } else { [0; 4] };
+                    assert_eq!(
+                        image.get_pixel(px, py).0,
+                        expected,
+                        "{} at {}, {}",
+                        name,
+                        px,
+                        py
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn missing_sprites_sample_the_white_pixel() {
+        let atlas = Atlas::pack(vec![sprite(8, 8, 10)]).unwrap();
+        let color = Vec4::new(0.5, 0.25, 1.0, 1.0);
+        let missing = atlas.sprite("ball", color);
+        assert_eq!(missing.color, color);
+        assert_eq!(missing.uv_rect.z, 0.0);
+        let (width, height) = atlas.image().dimensions();
+        let x = (missing.uv_rect.x * width as f32) as u32;
+        let y = (missing.uv_rect.y * height as f32) as u32;
+        assert_eq!(atlas.image().get_pixel(x, y).0, [255; 4]);
+
+        // Skin sprites show as they are.
+        assert_eq!(atlas.sprite("sprite10", color).color, Vec4::one());
+    }
+
+    #[test]
+    fn sprites_too_large_for_the_atlas_are_an_error() {
+        assert!(Atlas::pack(vec![sprite(MAX_SIZE, 1, 1)]).is_err());
+    }
+
+    #[test]
+    fn default_skin_has_tinted_ball_and_paddle() {
+        let atlas = Atlas::default_skin().unwrap();
+        let color = Vec4::new(0.5, 0.25, 1.0, 1.0);
+        for name in &["ball", "paddle"] {
+            assert!(atlas.contains(name), "{}", name);
+            assert_eq!(atlas.sprite(name, color).color, color);
+        }
+        assert!(!atlas.contains("background"));
+    }
+}
@@ -1,22 +1,24 @@
-#![allow(clippy::identity_op)]
-
+use super::atlas::Sprite;
+use super::vertex_layout::{self, Attribute, Format};
 use crate::{
-    math::Vec2,
+    math::{Vec2, Vec4},
     state::{Ball, Player},
-    util::size_of_slice,
 };
 use bytemuck::{Pod, Zeroable};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    BufferAddress, InputStepMode, VertexBufferDescriptor,
+    BufferAddress, InputStepMode, VertexAttributeDescriptor, VertexBufferDescriptor, VertexFormat,
+    COPY_BUFFER_ALIGNMENT,
 };
 
-pub const U32_SIZE: wgpu::BufferAddress = std::mem::size_of::<u32>() as wgpu::BufferAddress;
-
+/// Corner of the unit quad every entity is drawn from.
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone)]
 pub struct Vertex {
     pub pos: Vec2,
+    pub tex_coord: Vec2,
 }
 
 impl Vertex {
@@ -24,104 +26,342 @@ impl Vertex {
     pub const DESC: VertexBufferDescriptor<'static> = VertexBufferDescriptor {
         stride: Self::SIZE,
         step_mode: InputStepMode::Vertex,
-        attributes: &wgpu::vertex_attr_array![0 => Float2],
+        attributes: &[
+            attribute(vertex_layout::VERTEX[0]),
+            attribute(vertex_layout::VERTEX[1]),
+        ],
     };
 }
 
-pub struct QuadBufferBuilder {
-    vertex_data: Vec<Vertex>,
-    index_data: Vec<u32>,
-    current_quad: u32,
+/// Turns an input of `vertex_layout`, the table `build.rs` checks the shader
+/// against, into its wgpu description.
+const fn attribute(attribute: Attribute) -> VertexAttributeDescriptor {
+    VertexAttributeDescriptor {
+        offset: attribute.offset,
+        format: match attribute.format {
+            Format::Float => VertexFormat::Float,
+            Format::Float2 => VertexFormat::Float2,
+            Format::Float4 => VertexFormat::Float4,
+        },
+        shader_location: attribute.location,
+    }
+}
+
+/// A quad centred on the origin with sides of length 1, scaled, rotated and
+/// moved into place by each `QuadInstance`.
+pub const UNIT_QUAD_VERTICES: &[Vertex] = &[
+    Vertex {
+        pos: Vec2 { x: -0.5, y: -0.5 },
+        tex_coord: Vec2 { x: 0.0, y: 1.0 },
+    },
+    Vertex {
+        pos: Vec2 { x: 0.5, y: -0.5 },
+        tex_coord: Vec2 { x: 1.0, y: 1.0 },
+    },
+    Vertex {
+        pos: Vec2 { x: 0.5, y: 0.5 },
+        tex_coord: Vec2 { x: 1.0, y: 0.0 },
+    },
+    Vertex {
+        pos: Vec2 { x: -0.5, y: 0.5 },
+        tex_coord: Vec2 { x: 0.0, y: 0.0 },
+    },
+];
+pub const UNIT_QUAD_INDICES: &[u32] = &[0, 1, 2, 0, 2, 3];
+
+/// One drawn entity: where the unit quad goes and what it shows.
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone)]
+pub struct QuadInstance {
+    /// Centre in playfield units.
+    pub position: Vec2,
+    pub size: Vec2,
+    pub color: Vec4,
+    /// Counter-clockwise, in radians.
+    pub rotation: f32,
+    /// Region of the atlas stretched over the quad, see `Atlas`.
+    pub uv_rect: Vec4,
 }
 
-impl QuadBufferBuilder {
+impl QuadInstance {
+    pub const SIZE: BufferAddress = std::mem::size_of::<Self>() as BufferAddress;
+    pub const DESC: VertexBufferDescriptor<'static> = VertexBufferDescriptor {
+        stride: Self::SIZE,
+        step_mode: InputStepMode::Instance,
+        attributes: &[
+            attribute(vertex_layout::INSTANCE[0]),
+            attribute(vertex_layout::INSTANCE[1]),
+            attribute(vertex_layout::INSTANCE[2]),
+            attribute(vertex_layout::INSTANCE[3]),
+            attribute(vertex_layout::INSTANCE[4]),
+        ],
+    };
+}
+
+/// Collects the instances of a frame. Kept by the renderer and cleared
+/// every frame, so its storage is reused once it has grown large enough.
+pub struct InstanceBufferBuilder {
+    instance_data: Vec<QuadInstance>,
+}
+
+impl InstanceBufferBuilder {
     pub fn new() -> Self {
         Self {
-            vertex_data: Vec::new(),
-            index_data: Vec::new(),
-            current_quad: 0,
+            instance_data: Vec::new(),
         }
     }
 
-    pub fn push_ball(self, ball: &Ball) -> Self {
+    pub fn clear(&mut self) {
+        self.instance_data.clear();
+    }
+
+    /// Covers the whole playfield, so push it before anything drawn on top.
+    pub fn push_background(&mut self, sprite: Sprite) {
+        self.push_instance(QuadInstance {
+            position: Vec2::zero(),
+            size: Vec2::new(2.0, 2.0),
+            color: sprite.color,
+            rotation: 0.0,
+            uv_rect: sprite.uv_rect,
+        });
+    }
+
+    pub fn push_ball(&mut self, ball: &Ball, sprite: Sprite) {
         if ball.visible {
-            let min_x = ball.position.x - ball.radius;
-            let min_y = ball.position.y - ball.radius;
-            let max_x = ball.position.x + ball.radius;
-            let max_y = ball.position.y + ball.radius;
-
-            self.push_quad(min_x, min_y, max_x, max_y)
-        } else {
-            self
+            let diameter = ball.radius * 2.0;
+            self.push_instance(QuadInstance {
+                position: ball.position,
+                size: Vec2::new(diameter, diameter),
+                color: sprite.color,
+                rotation: 0.0,
+                uv_rect: sprite.uv_rect,
+            });
         }
     }
 
-    pub fn push_player(self, player: &Player) -> Self {
+    pub fn push_player(&mut self, player: &Player, sprite: Sprite) {
         if player.visible {
-            self.push_quad(
-                player.position.x - player.size.x * 0.5,
-                player.position.y - player.size.y * 0.5,
-                player.position.x + player.size.x * 0.5,
-                player.position.y + player.size.y * 0.5,
-            )
-        } else {
-            self
+            self.push_instance(QuadInstance {
+                position: player.position,
+                size: player.size,
+                color: sprite.color,
+                rotation: 0.0,
+                uv_rect: sprite.uv_rect,
+            });
         }
     }
 
-    pub fn push_quad(mut self, min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> Self {
-        self.vertex_data.extend(&[
-            Vertex {
-                pos: (min_x, min_y).into(),
-            },
-            Vertex {
-                pos: (max_x, min_y).into(),
-            },
-            Vertex {
-                pos: (max_x, max_y).into(),
-            },
-            Vertex {
-                pos: (min_x, max_y).into(),
-            },
-        ]);
-        self.index_data.extend(&[
-            self.current_quad * 4 + 0,
-            self.current_quad * 4 + 1,
-            self.current_quad * 4 + 2,
-            self.current_quad * 4 + 0,
-            self.current_quad * 4 + 2,
-            self.current_quad * 4 + 3,
-        ]);
-        self.current_quad += 1;
-        self
-    }
-    pub fn build(self, device: &wgpu::Device) -> (StagingBuffer, StagingBuffer, u32) {
-        (
-            StagingBuffer::new(device, &self.vertex_data),
-            StagingBuffer::new(device, &self.index_data),
-            self.index_data.len() as u32,
-        )
+    pub fn push_instance(&mut self, instance: QuadInstance) {
+        self.instance_data.push(instance);
+    }
+
+    pub fn instances(&self) -> &[QuadInstance] {
+        &self.instance_data
     }
 }
 
-pub struct StagingBuffer {
+/// GPU buffer kept across frames and written through the queue. Its
+/// capacity doubles whenever the data no longer fits, so a new buffer is
+/// only created while the data is still growing.
+pub struct GrowableBuffer {
     buffer: wgpu::Buffer,
-    size: wgpu::BufferAddress,
+    /// Size of `buffer` in bytes.
+    capacity: BufferAddress,
+    usage: wgpu::BufferUsage,
+    label: &'static str,
 }
 
-impl StagingBuffer {
-    pub fn new<T: bytemuck::Pod + Sized>(device: &wgpu::Device, data: &[T]) -> StagingBuffer {
-        StagingBuffer {
-            buffer: device.create_buffer_init(&BufferInitDescriptor {
-                contents: bytemuck::cast_slice(data),
-                usage: wgpu::BufferUsage::COPY_SRC,
-                label: Some("Staging Buffer"),
-            }),
-            size: size_of_slice(data) as wgpu::BufferAddress,
+impl GrowableBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsage,
+        capacity: BufferAddress,
+    ) -> Self {
+        let usage = usage | wgpu::BufferUsage::COPY_DST;
+        Self {
+            buffer: create_buffer(device, label, usage, capacity),
+            capacity,
+            usage,
+            label,
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Writes `data` to the start of the buffer, replacing it by a larger
+    /// one first when it doesn't fit. Returns whether a new buffer was
+    /// created.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[u8]) -> bool {
+        let size = data.len() as BufferAddress;
+        let grow = size > self.capacity;
+        if grow {
+            let mut capacity = self.capacity.max(COPY_BUFFER_ALIGNMENT);
+            while capacity < size {
+                capacity *= 2;
+            }
+            log::debug!(
+                "Growing {} from {} to {} bytes",
+                self.label,
+                self.capacity,
+                capacity
+            );
+            self.buffer = create_buffer(device, self.label, self.usage, capacity);
+            self.capacity = capacity;
+        }
+        if size > 0 {
+            queue.write_buffer(&self.buffer, 0, data);
         }
+        grow
+    }
+}
+
+/// File wgpu writes its trace to, in the directory given to
+/// `request_device`.
+const TRACE_FILE: &str = "trace.ron";
+/// Start of the trace entry of every `Device::create_buffer`.
+const CREATE_BUFFER: &[u8] = b"CreateBuffer(";
+
+/// Counts every GPU buffer the device creates, including the staging belt
+/// chunks and the glyph brush buffers that wgpu and wgpu_glyph create out
+/// of the renderer's reach. wgpu records every call in its trace, so the
+/// counter reads the entries added since it last looked. Tracing writes
+/// every upload to disk as well, so it is only set up for `--buffer-stats`.
+pub struct BufferCounter {
+    /// `None` when the device doesn't trace.
+    trace: Option<File>,
+    /// Trace read so far and not counted yet, the last line may still be
+    /// incomplete.
+    pending: Vec<u8>,
+}
+
+impl BufferCounter {
+    /// Follows the trace of a device created with `dir` as trace path,
+    /// counting only the buffers created from now on.
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(TRACE_FILE);
+        let trace = File::open(&path)
+            .and_then(|mut trace| {
+                trace.seek(SeekFrom::End(0))?;
+                Ok(trace)
+            })
+            .map_err(|e| log::warn!("Not counting GPU buffers, no {}: {}", path.display(), e))
+            .ok();
+        Self {
+            trace,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn is_counting(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Buffers created since the previous call.
+    pub fn take(&mut self) -> u32 {
+        if let Some(trace) = &mut self.trace {
+            if let Err(e) = trace.read_to_end(&mut self.pending) {
+                log::warn!("Stopped counting GPU buffers: {}", e);
+                self.trace = None;
+            }
+        }
+        let end = match self.pending.iter().rposition(|&byte| byte == b'\n') {
+            Some(newline) => newline + 1,
+            None => return 0,
+        };
+        let created = count_created(&self.pending[..end]);
+        self.pending.drain(..end);
+        created
+    }
+}
+
+fn count_created(trace: &[u8]) -> u32 {
+    trace
+        .windows(CREATE_BUFFER.len())
+        .filter(|window| *window == CREATE_BUFFER)
+        .count() as u32
+}
+
+fn create_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    usage: wgpu::BufferUsage,
+    size: BufferAddress,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Byte offset and size of a field of `value`, like `memoffset` gives.
+    fn field<T, F>(value: &T, field: &F) -> (u64, u64) {
+        let offset = field as *const F as usize - value as *const T as usize;
+        (offset as u64, std::mem::size_of::<F>() as u64)
+    }
+
+    /// Checks that the `vertex_layout` table reads exactly `fields`, in
+    /// order, and that they fill the whole struct.
+    fn assert_layout(attributes: &[Attribute], fields: &[(u64, u64)], size: BufferAddress) {
+        assert_eq!(attributes.len(), fields.len());
+        for (attribute, &(offset, field_size)) in attributes.iter().zip(fields) {
+            assert_eq!(attribute.offset, offset, "{:?}", attribute);
+            assert_eq!(attribute.format.size(), field_size, "{:?}", attribute);
+        }
+        assert_eq!(fields.iter().map(|(_, size)| size).sum::<u64>(), size);
+    }
+
+    #[test]
+    fn vertex_layout_matches_vertex() {
+        let vertex = Vertex::zeroed();
+        assert_layout(
+            &vertex_layout::VERTEX,
+            &[
+                field(&vertex, &vertex.pos),
+                field(&vertex, &vertex.tex_coord),
+            ],
+            Vertex::SIZE,
+        );
+    }
+
+    #[test]
+    fn vertex_layout_matches_quad_instance() {
+        let instance = QuadInstance::zeroed();
+        assert_layout(
+            &vertex_layout::INSTANCE,
+            &[
+                field(&instance, &instance.position),
+                field(&instance, &instance.size),
+                field(&instance, &instance.color),
+                field(&instance, &instance.rotation),
+                field(&instance, &instance.uv_rect),
+            ],
+            QuadInstance::SIZE,
+        );
     }
 
-    pub fn copy_to_buffer(&self, encoder: &mut wgpu::CommandEncoder, other: &wgpu::Buffer) {
-        encoder.copy_buffer_to_buffer(&self.buffer, 0, other, 0, self.size)
+    #[test]
+    fn counts_buffers_of_complete_trace_lines() {
+        let mut counter = BufferCounter {
+            trace: None,
+            pending: Vec::new(),
+        };
+        counter.pending.extend_from_slice(
+            b"CreateBuffer(\n    (1, 1, Vulkan),\n),\nWriteBuffer(\n),\nCreateBuf",
+        );
+        assert_eq!(counter.take(), 1);
+        counter
+            .pending
+            .extend_from_slice(b"fer(\n),\nCreateTexture(\n),\n");
+        assert_eq!(counter.take(), 1);
+        assert_eq!(counter.take(), 0);
     }
 }
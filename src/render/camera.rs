@@ -0,0 +1,48 @@
+use bytemuck::{Pod, Zeroable};
+
+/// Maps the square playfield, -1 to 1 on both axes, to the largest square
+/// centred on the surface. The rest of the surface is left as bars.
+#[derive(Debug, Copy, Clone)]
+pub struct Camera {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Camera {
+    /// Side of the playfield square in pixels.
+    pub fn field_size(&self) -> f32 {
+        self.width.min(self.height)
+    }
+
+    /// Pixel rectangle `(x, y, width, height)` covered by the playfield.
+    pub fn field_rect(&self) -> (u32, u32, u32, u32) {
+        let size = self.field_size();
+        let x = (self.width - size) * 0.5;
+        let y = (self.height - size) * 0.5;
+        (x as u32, y as u32, size as u32, size as u32)
+    }
+
+    pub fn uniform(&self) -> CameraUniform {
+        let size = self.field_size();
+        let (scale_x, scale_y) = if size > 0.0 {
+            (size / self.width, size / self.height)
+        } else {
+            (1.0, 1.0)
+        };
+        CameraUniform {
+            view_proj: [
+                [scale_x, 0.0, 0.0, 0.0],
+                [0.0, scale_y, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+}
+
+/// Layout of the `Camera` uniform block in `shader.vert`.
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone)]
+pub struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+}
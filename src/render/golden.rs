@@ -0,0 +1,137 @@
+//! Golden image checks: renders a fixed set of screens with the headless
+//! renderer and compares them against PNGs checked into a directory.
+
+use eyre::*;
+use std::path::Path;
+
+use super::Renderer;
+use crate::input::Input;
+use crate::rules::MatchRules;
+use crate::sim::{Difficulty, GameState, Simulation, State};
+use crate::util;
+
+pub const WIDTH: u32 = 400;
+pub const HEIGHT: u32 = 300;
+/// Largest per channel difference for two pixels to count as equal.
+pub const CHANNEL_TOLERANCE: u8 = 8;
+/// Share of the pixels that may differ before a frame fails.
+pub const MAX_DIFFERENT_PIXELS: f32 = 0.001;
+
+const SEED: u64 = 1;
+const MAX_TICKS: usize = 60 * 60;
+
+/// Builds the states of every checked screen, named after their PNG.
+pub fn scenes() -> Result<Vec<(&'static str, State)>> {
+    let mut state = State::new(WIDTH as f32, HEIGHT as f32, SEED);
+    state.difficulty = Difficulty::Hard;
+    state.rules = MatchRules {
+        points_to_win: 1,
+        ..MatchRules::default()
+    };
+    let mut simulation = Simulation::new(&mut state);
+    let mut events = Vec::new();
+    let mut scenes = vec![("menu", state.clone())];
+
+    let confirm = Input {
+        enter_pressed: true,
+        ..Input::default()
+    };
+    simulation.tick(&confirm, &mut state, &mut events, util::TIMESTEP);
+    simulation.tick(&Input::default(), &mut state, &mut events, util::TIMESTEP);
+    scenes.push(("serving", state.clone()));
+
+    for (name, game_state) in &[
+        ("playing", GameState::Playing),
+        ("game_over", GameState::GameOver),
+    ] {
+        let mut ticks = 0;
+        while state.game_state != *game_state {
+            ensure!(ticks < MAX_TICKS, "The {} screen was never reached.", name);
+            simulation.tick(&Input::default(), &mut state, &mut events, util::TIMESTEP);
+            ticks += 1;
+        }
+        // Let the screen settle so the frame is not the first one after a change.
+        for _ in 0..10 {
+            simulation.tick(&Input::default(), &mut state, &mut events, util::TIMESTEP);
+        }
+        scenes.push((name, state.clone()));
+    }
+    Ok(scenes)
+}
+
+/// Whether wgpu finds an adapter to render the scenes with. Machines
+/// without one, not even a software driver, can't run the check.
+pub async fn adapter_available() -> bool {
+    wgpu::Instance::new(wgpu::BackendBit::PRIMARY)
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::Default,
+            compatible_surface: None,
+        })
+        .await
+        .is_some()
+}
+
+/// Counts the pixels of `a` and `b` that differ by more than the tolerance.
+pub fn count_different_pixels(a: &image::RgbaImage, b: &image::RgbaImage) -> usize {
+    a.pixels()
+        .zip(b.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .any(|(a, b)| (*a as i16 - *b as i16).abs() > CHANNEL_TOLERANCE as i16)
+        })
+        .count()
+}
+
+/// Renders every scene and compares it with `<dir>/<name>.png`. With
+/// `bless` the goldens are written instead.
+pub async fn run(dir: &Path, bless: bool) -> Result<()> {
+    let mut renderer = Renderer::new_headless(WIDTH, HEIGHT).await?;
+    let mut failures = Vec::new();
+
+    for (name, state) in scenes()? {
+        let frame = renderer.capture_state(&state, &state, 1.0)?;
+        let path = dir.join(format!("{}.png", name));
+
+        if bless {
+            std::fs::create_dir_all(dir)?;
+            frame.save(&path)?;
+            println!("Blessed {}", path.display());
+            continue;
+        }
+
+        let golden = image::open(&path)
+            .wrap_err_with(|| format!("Failed to open golden image {}", path.display()))?
+            .into_rgba8();
+        if golden.dimensions() != frame.dimensions() {
+            failures.push(format!(
+                "{}: expected {:?} pixels, rendered {:?}",
+                name,
+                golden.dimensions(),
+                frame.dimensions()
+            ));
+            continue;
+        }
+
+        let different = count_different_pixels(&golden, &frame);
+        if different as f32 > MAX_DIFFERENT_PIXELS * (WIDTH * HEIGHT) as f32 {
+            let actual = dir.join(format!("{}.actual.png", name));
+            frame.save(&actual)?;
+            failures.push(format!(
+                "{}: {} pixels differ, frame written to {}",
+                name,
+                different,
+                actual.display()
+            ));
+        } else {
+            println!("{} matches", name);
+        }
+    }
+
+    ensure!(
+        failures.is_empty(),
+        "Golden images differ:\n{}",
+        failures.join("\n")
+    );
+    Ok(())
+}
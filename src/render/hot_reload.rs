@@ -0,0 +1,63 @@
+//! Watches the shader sources during development, so edits show up without
+//! rebuilding the game. Built with the `hot-reload` feature only.
+
+use eyre::*;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+/// Editors often write a file in several steps, changes closer together
+/// than this are reported once.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+pub struct ShaderWatcher {
+    // Dropping the watcher stops the events.
+    _watcher: RecommendedWatcher,
+    events: Receiver<DebouncedEvent>,
+    dir: PathBuf,
+}
+
+impl ShaderWatcher {
+    /// Watches the `shaders` directory of the source tree.
+    pub fn new() -> Result<Self> {
+        Self::watch(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"))
+    }
+
+    pub fn watch(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        let (sender, events) = channel();
+        let mut watcher: RecommendedWatcher = Watcher::new(sender, DEBOUNCE)?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .wrap_err_with(|| format!("Failed to watch {}", dir.display()))?;
+        log::info!("Watching {} for shader changes", dir.display());
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            dir,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether a shader changed since the last call. Never blocks.
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                DebouncedEvent::Create(_)
+                | DebouncedEvent::Write(_)
+                | DebouncedEvent::Rename(_, _)
+                | DebouncedEvent::Remove(_) => changed = true,
+                DebouncedEvent::Error(e, path) => {
+                    log::warn!("Failed to watch {:?} for shader changes: {}", path, e)
+                }
+                _ => {}
+            }
+        }
+        changed
+    }
+}
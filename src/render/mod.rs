@@ -1,53 +1,94 @@
-use crate::include_str_from_outdir;
 use eyre::*;
+use futures::task::SpawnExt;
+use std::path::{Path, PathBuf};
 use wgpu::{util::*, *};
 use wgpu_glyph::{ab_glyph, Section, Text};
 use winit::{monitor::VideoMode, window::Window};
 
-use crate::math::Vec2;
+use crate::math::Vec4;
 use crate::state;
+use crate::util;
 
+mod atlas;
 mod buffers;
+mod camera;
+pub mod golden;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+mod vertex_layout;
+use atlas::*;
 use buffers::*;
+use camera::*;
 
 pub const SHADER_ENTRY_POINT_NAME: &str = "main";
+/// Directory in the system temp dir that wgpu writes the device trace to
+/// with `--buffer-stats`.
+const TRACE_DIR_NAME: &str = "gnip-gnop-trace";
+/// Entities the instance buffer holds before it has to grow.
+const INITIAL_INSTANCES: BufferAddress = 64;
 
 const FONT_BYTES: &[u8] = include_bytes!("../../res/fonts/PressStart2P-Regular.ttf");
 
-#[rustfmt::skip]
-const VERTEXES: &[Vertex; 6] = &[
-    Vertex { pos: Vec2 { x:  0.0, y:  0.5 } },
-    Vertex { pos: Vec2 { x: -0.5, y: -0.5 } },
-    Vertex { pos: Vec2 { x: -0.5, y:  0.5 } },
-    Vertex { pos: Vec2 { x:  0.0, y: -0.5 } },
-    Vertex { pos: Vec2 { x:  0.5, y: -0.5 } },
-    Vertex { pos: Vec2 { x:  0.5, y:  0.5 } },
-
-];
-
-const INDEXES: &[u32; 6] = &[0, 2, 1, 3, 4, 5];
+/// Where finished frames go.
+enum Output {
+    Window {
+        surface: Surface,
+        swap_chain: SwapChain,
+        /// Swap chain frames cannot be copied, captured frames are drawn
+        /// a second time into this texture instead. Created by the first
+        /// capture, so windows that never capture don't pay for it.
+        capture: Option<Texture>,
+    },
+    Offscreen {
+        texture: Texture,
+    },
+}
 
 pub struct Renderer {
-    surface: Surface,
     adapter: Adapter,
     device: Device,
     queue: Queue,
 
-    swap_chain: SwapChain,
+    output: Output,
     sc_desc: SwapChainDescriptor,
 
     render_pipeline: RenderPipeline,
     rp_layout: PipelineLayout,
 
-    basic_vertex_buffer: Buffer,
-    basic_index_buffer: Buffer,
-    num_indices: u32,
+    camera: Camera,
+    camera_buffer: Buffer,
+    camera_bind_group: BindGroup,
 
+    /// Sprites of the current skin, sampled by every quad.
+    atlas: Atlas,
+    atlas_bind_group_layout: BindGroupLayout,
+    atlas_bind_group: BindGroup,
+
+    /// The unit quad, drawn once per instance.
     vertex_buffer: Buffer,
     index_buffer: Buffer,
+    instance_buffer: GrowableBuffer,
+    instances: InstanceBufferBuilder,
 
     glyph_brush: wgpu_glyph::GlyphBrush<()>,
     staging_belt: StagingBelt,
+    /// Runs the futures that hand used staging belt chunks back for reuse.
+    local_pool: futures::executor::LocalPool,
+
+    /// Why the last shader reload failed, shown over the game until a
+    /// reload succeeds.
+    shader_error: Option<state::Text>,
+
+    /// Reads the device trace with `--buffer-stats`, which also has the
+    /// buffers created inside wgpu and wgpu_glyph. `buffers_created` is
+    /// drawn in the corner of the screen while it is set.
+    buffer_counter: Option<BufferCounter>,
+    /// GPU buffers the renderer itself created since the last frame was
+    /// finished, captures of the frame included.
+    frame_buffers: u32,
+    /// GPU buffers created for the last finished frame, zero once every
+    /// buffer has grown to what the frames need.
+    buffers_created: u32,
 }
 
 impl Renderer {
@@ -55,128 +96,170 @@ impl Renderer {
         self.sc_desc.width as f32
     }
 
-    #[allow(dead_code)]
     pub fn height(&self) -> f32 {
         self.sc_desc.height as f32
     }
 
-    pub async fn new(window: &Window, video_mode: &VideoMode) -> Result<Self> {
+    /// GPU buffers created for the last frame, captures included. Reads
+    /// zero in steady state. Only counts the renderer's own buffers unless
+    /// the device is traced, see `Renderer::new`.
+    pub fn buffers_created(&self) -> u32 {
+        self.buffers_created
+    }
+
+    /// Creates a renderer drawing into `window`. With `vsync` frames wait
+    /// for the display refresh. With `count_buffers` the device is traced
+    /// to count the buffers wgpu and wgpu_glyph create as well, and the
+    /// count is shown in the bottom left corner.
+    pub async fn new(
+        window: &Window,
+        video_mode: &VideoMode,
+        vsync: bool,
+        count_buffers: bool,
+    ) -> Result<Self> {
         let backend_bit = BackendBit::PRIMARY;
         let instance = Instance::new(backend_bit);
-        println!(
-            "All available adapters that match {:?} backends: {:?}",
-            backend_bit,
-            instance
-                .enumerate_adapters(BackendBit::PRIMARY)
-                .collect::<Vec<_>>()
-        );
-
         let surface = unsafe { instance.create_surface(window) };
-
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::Default,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .ok_or_else(|| {
-                eyre!(
-                    "Failed to provide adapter for the {:?} backend bit.",
-                    backend_bit
-                )
-            })?;
-
-        let (device, queue) = adapter
-            .request_device(
-                &DeviceDescriptor {
-                    features: Features::default(),
-                    limits: Limits::default(),
-                    shader_validation: true,
-                },
-                Some(std::path::Path::new(env!("OUT_DIR"))),
-            )
-            .await?;
+        let trace_dir = if count_buffers {
+            Some(trace_dir()?)
+        } else {
+            None
+        };
+        let (adapter, device, queue) =
+            request_device(&instance, Some(&surface), trace_dir.as_deref()).await?;
 
         let size = video_mode.size();
         let sc_desc = SwapChainDescriptor {
             usage: TextureUsage::OUTPUT_ATTACHMENT,
-            present_mode: PresentMode::Fifo,
+            present_mode: present_mode(vsync),
             format: TextureFormat::Bgra8UnormSrgb,
             width: size.width,
             height: size.height,
         };
 
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        let output = Output::Window {
+            surface,
+            swap_chain,
+            capture: None,
+        };
+        let buffer_counter = trace_dir.as_deref().map(BufferCounter::open);
+        Self::with_output(adapter, device, queue, output, sc_desc, buffer_counter)
+    }
 
-        let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("Main Render Pipeline Layout Descriptor"),
-            bind_group_layouts: &[],
-            push_constant_ranges: &[],
+    /// Creates a renderer without a window that draws into a `width` x
+    /// `height` texture. Any adapter will do, software ones included.
+    pub async fn new_headless(width: u32, height: u32) -> Result<Self> {
+        let instance = Instance::new(BackendBit::PRIMARY);
+        let (adapter, device, queue) = request_device(&instance, None, None).await?;
+
+        let sc_desc = SwapChainDescriptor {
+            usage: TextureUsage::OUTPUT_ATTACHMENT,
+            present_mode: PresentMode::Fifo,
+            format: TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+        };
+        let output = Output::Offscreen {
+            texture: create_offscreen_texture(&device, &sc_desc),
+        };
+        Self::with_output(adapter, device, queue, output, sc_desc, None)
+    }
+
+    fn with_output(
+        adapter: Adapter,
+        device: Device,
+        queue: Queue,
+        output: Output,
+        sc_desc: SwapChainDescriptor,
+        buffer_counter: Option<BufferCounter>,
+    ) -> Result<Self> {
+        let camera = Camera {
+            width: sc_desc.width as f32,
+            height: sc_desc.height as f32,
+        };
+        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera.uniform()]),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
         });
-        let basic_vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            usage: BufferUsage::VERTEX,
-            contents: bytemuck::cast_slice(VERTEXES),
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Camera Bind Group Layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::VERTEX,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(camera_buffer.slice(..)),
+            }],
         });
 
-        let basic_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            usage: BufferUsage::INDEX,
-            contents: bytemuck::cast_slice(INDEXES),
+        let atlas_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Atlas Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+            ],
         });
-        let num_indices = INDEXES.len() as u32;
+        let atlas = Atlas::default_skin()?;
+        let atlas_bind_group =
+            create_atlas_bind_group(&device, &queue, &atlas_bind_group_layout, &atlas);
 
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: Vertex::SIZE * 4 * 3,
-            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-            mapped_at_creation: false,
+        let rp_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Main Render Pipeline Layout Descriptor"),
+            bind_group_layouts: &[&camera_bind_group_layout, &atlas_bind_group_layout],
+            push_constant_ranges: &[],
         });
-
-        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: U32_SIZE * 6 * 3,
-            usage: wgpu::BufferUsage::INDEX | wgpu::BufferUsage::COPY_DST,
-            mapped_at_creation: false,
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Unit Quad Vertex Buffer"),
+            contents: bytemuck::cast_slice(UNIT_QUAD_VERTICES),
+            usage: wgpu::BufferUsage::VERTEX,
         });
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Unit Quad Index Buffer"),
+            contents: bytemuck::cast_slice(UNIT_QUAD_INDICES),
+            usage: wgpu::BufferUsage::INDEX,
+        });
+        let instance_buffer = GrowableBuffer::new(
+            &device,
+            "Instance Buffer",
+            wgpu::BufferUsage::VERTEX,
+            QuadInstance::SIZE * INITIAL_INSTANCES,
+        );
 
-        let mut shader_compiler =
-            shaderc::Compiler::new().ok_or_else(|| eyre!("Failed to create shader compiler."))?;
-        let render_pipeline = {
-            let vs_src = include_str_from_outdir!("/shaders/shader.vert");
-            let fs_src = include_str_from_outdir!("/shaders/shader.frag");
-            let vs_spirv = shader_compiler
-                .compile_into_spirv(
-                    vs_src,
-                    shaderc::ShaderKind::Vertex,
-                    "shader.vert",
-                    SHADER_ENTRY_POINT_NAME,
-                    None,
-                )
-                .unwrap();
-            let fs_spirv = shader_compiler
-                .compile_into_spirv(
-                    fs_src,
-                    shaderc::ShaderKind::Fragment,
-                    "shader.frag",
-                    SHADER_ENTRY_POINT_NAME,
-                    None,
-                )
-                .unwrap();
-            let vs_module =
-                device.create_shader_module(wgpu::util::make_spirv(&vs_spirv.as_binary_u8()));
-            let fs_module =
-                device.create_shader_module(wgpu::util::make_spirv(&fs_spirv.as_binary_u8()));
-
-            create_render_pipeline(
-                &device,
-                &rp_layout,
-                sc_desc.format,
-                &[Vertex::DESC],
-                vs_module,
-                fs_module,
-            )
-        };
+        // compiled to SPIR-V by build.rs
+        let render_pipeline = spirv_render_pipeline(
+            &device,
+            &rp_layout,
+            sc_desc.format,
+            include_bytes_from_outdir!("/shaders/shader.vert.spv"),
+            include_bytes_from_outdir!("/shaders/shader.frag.spv"),
+        );
 
         let font = ab_glyph::FontArc::try_from_slice(FONT_BYTES).unwrap();
         let glyph_brush =
@@ -184,130 +267,409 @@ impl Renderer {
         let staging_belt = wgpu::util::StagingBelt::new(1024);
 
         Ok(Self {
-            surface,
             adapter,
             device,
             queue,
-            swap_chain,
+            output,
             sc_desc,
             render_pipeline,
             rp_layout,
 
-            basic_vertex_buffer,
-            basic_index_buffer,
-            num_indices,
+            camera,
+            camera_buffer,
+            camera_bind_group,
+
+            atlas,
+            atlas_bind_group_layout,
+            atlas_bind_group,
 
             vertex_buffer,
             index_buffer,
+            instance_buffer,
+            instances: InstanceBufferBuilder::new(),
 
             glyph_brush,
             staging_belt,
+            local_pool: futures::executor::LocalPool::new(),
+
+            shader_error: None,
+            buffer_counter,
+            frame_buffers: 0,
+            buffers_created: 0,
         })
     }
 
-    pub fn render_basic(&mut self, frame: &SwapChainTexture, mut encoder: CommandEncoder) {
-        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            color_attachments: &[RenderPassColorAttachmentDescriptor {
-                attachment: &frame.view,
-                resolve_target: None,
-                ops: Operations {
-                    load: LoadOp::Clear(Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
-                    store: true,
-                },
-            }],
-            depth_stencil_attachment: None,
-        });
-
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_vertex_buffer(0, self.basic_vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.basic_index_buffer.slice(..));
-        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-
-        drop(render_pass);
+    /// Draws the ball, the paddles and the playfield background with the
+    /// `ball.png`, `paddle.png` and `background.png` in `dir` instead of the
+    /// built-in sprites. Missing images are drawn as plain quads of their
+    /// theme colour; on errors the current skin stays.
+    pub fn load_skin(&mut self, dir: &Path) -> Result<()> {
+        let atlas = Atlas::load(dir)?;
+        log::info!("Loaded {} sprites from {}", atlas.len(), dir.display());
+        self.atlas_bind_group = create_atlas_bind_group(
+            &self.device,
+            &self.queue,
+            &self.atlas_bind_group_layout,
+            &atlas,
+        );
+        self.atlas = atlas;
+        Ok(())
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
+    /// Compiles `shader.vert` and `shader.frag` from `dir` again and swaps
+    /// them into the pipeline. On errors, a vertex shader reading inputs
+    /// `vertex_layout` doesn't provide included, the old pipeline stays and
+    /// the error is logged and shown on screen.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders(&mut self, dir: &Path) {
+        let pipeline = std::fs::read_to_string(dir.join("shader.vert"))
+            .and_then(|vs_src| Ok((vs_src, std::fs::read_to_string(dir.join("shader.frag"))?)))
+            .map_err(Report::from)
+            .and_then(|(vs_src, fs_src)| {
+                compile_render_pipeline(
+                    &self.device,
+                    &self.rp_layout,
+                    self.sc_desc.format,
+                    &vs_src,
+                    &fs_src,
+                )
+            });
+        match pipeline {
+            Ok(pipeline) => {
+                log::info!("Reloaded the shaders from {}", dir.display());
+                self.render_pipeline = pipeline;
+                self.shader_error = None;
+            }
+            Err(e) => {
+                log::error!("Failed to reload the shaders: {:?}", e);
+                self.shader_error = Some(state::Text {
+                    position: (10.0, 10.0).into(),
+                    bounds: (self.width() - 20.0, state::UNBOUNDED_F32).into(),
+                    color: (1.0, 0.2, 0.2, 1.0).into(),
+                    text: format!("{:#}", e),
+                    size: 12.0,
+                    visible: true,
+                    ..Default::default()
+                });
+            }
+        }
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        match &mut self.output {
+            Output::Window {
+                surface,
+                swap_chain,
+                capture,
+            } => {
+                *swap_chain = self.device.create_swap_chain(surface, &self.sc_desc);
+                if let Some(capture) = capture {
+                    *capture = create_offscreen_texture(&self.device, &self.sc_desc);
+                }
+            }
+            Output::Offscreen { texture } => {
+                *texture = create_offscreen_texture(&self.device, &self.sc_desc)
+            }
+        }
+
+        self.camera = Camera {
+            width: new_size.width as f32,
+            height: new_size.height as f32,
+        };
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera.uniform()]),
+        );
     }
 
-    pub fn render_state(&mut self, state: &state::State) -> Result<()> {
-        let frame = match self.swap_chain.get_current_frame() {
-            Ok(frame) => Ok(frame.output),
-            Err(wgpu::SwapChainError::Outdated) => {
-                self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
-                return Ok(());
+    /// Switches between waiting for the display refresh and presenting
+    /// frames as soon as they are done.
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.sc_desc.present_mode = present_mode(vsync);
+        if let Output::Window {
+            surface,
+            swap_chain,
+            ..
+        } = &mut self.output
+        {
+            *swap_chain = self.device.create_swap_chain(surface, &self.sc_desc);
+        }
+    }
+
+    /// Draws `state`, blending entity positions from `prev` by `alpha` so
+    /// motion stays smooth when the redraw rate differs from the tick rate.
+    pub fn render_state(
+        &mut self,
+        prev: &state::State,
+        state: &state::State,
+        alpha: f32,
+    ) -> Result<()> {
+        match &mut self.output {
+            Output::Window {
+                surface,
+                swap_chain,
+                ..
+            } => {
+                let frame = match swap_chain.get_current_frame() {
+                    Ok(frame) => frame.output,
+                    Err(wgpu::SwapChainError::Outdated) => {
+                        *swap_chain = self.device.create_swap_chain(surface, &self.sc_desc);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                self.draw(prev, state, alpha, &frame.view);
+            }
+            Output::Offscreen { texture } => {
+                let view = texture.create_view(&TextureViewDescriptor::default());
+                self.draw(prev, state, alpha, &view);
             }
-            Err(e) => Err(e),
-        }?;
+        }
+        // Captures of the frame were drawn before it, their buffers are in.
+        let own_buffers = std::mem::take(&mut self.frame_buffers);
+        self.buffers_created = match &mut self.buffer_counter {
+            // The trace has the renderer's own buffers as well.
+            Some(counter) if counter.is_counting() => counter.take().max(own_buffers),
+            _ => own_buffers,
+        };
+        if self.buffers_created != 0 {
+            log::debug!("Created {} GPU buffers this frame", self.buffers_created);
+        }
+        Ok(())
+    }
+
+    /// Draws `state` like `render_state`, into a texture that is then read
+    /// back as an RGBA image. Nothing is presented to the window.
+    pub fn capture_state(
+        &mut self,
+        prev: &state::State,
+        state: &state::State,
+        alpha: f32,
+    ) -> Result<image::RgbaImage> {
+        if let Output::Window { capture, .. } = &mut self.output {
+            if capture.is_none() {
+                *capture = Some(create_offscreen_texture(&self.device, &self.sc_desc));
+            }
+        }
+        let view = self
+            .capture_texture()
+            .ok_or_else(|| eyre!("Failed to create the capture texture."))?
+            .create_view(&TextureViewDescriptor::default());
+        self.draw(prev, state, alpha, &view);
+        let texture = self
+            .capture_texture()
+            .ok_or_else(|| eyre!("Failed to create the capture texture."))?;
+        let image = self.read_texture(texture);
+        // The readback buffer.
+        self.frame_buffers += 1;
+        image
+    }
+
+    fn capture_texture(&self) -> Option<&Texture> {
+        match &self.output {
+            Output::Window { capture, .. } => capture.as_ref(),
+            Output::Offscreen { texture } => Some(texture),
+        }
+    }
+
+    fn read_texture(&self, texture: &Texture) -> Result<image::RgbaImage> {
+        let (width, height) = (self.sc_desc.width, self.sc_desc.height);
+        let unpadded_bytes_per_row = width * 4;
+        let padding = (COPY_BYTES_PER_ROW_ALIGNMENT
+            - unpadded_bytes_per_row % COPY_BYTES_PER_ROW_ALIGNMENT)
+            % COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row + padding;
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as BufferAddress,
+            usage: BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         let mut encoder = self
             .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("State Command Encoder"),
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Readback Command Encoder"),
             });
+        encoder.copy_texture_to_buffer(
+            TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            BufferCopyView {
+                buffer: &buffer,
+                layout: TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_bytes_per_row,
+                    rows_per_image: height,
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
 
-        if state.game_state == state::GameState::Base {
-            self.render_basic(&frame, encoder);
-            return Ok(());
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(MapMode::Read);
+        self.device.poll(Maintain::Wait);
+        futures::executor::block_on(mapping)?;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        buffer.unmap();
+        if self.sc_desc.format == TextureFormat::Bgra8UnormSrgb {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
         }
 
-        let num_indices = if state.ball.visible || state.player1.visible || state.player2.visible {
-            let (stg_vertex, stg_index, num_indices) = QuadBufferBuilder::new()
-                .push_ball(&state.ball)
-                .push_player(&state.player1)
-                .push_player(&state.player2)
-                .build(&self.device);
+        image::RgbaImage::from_raw(width, height, pixels).ok_or_else(|| {
+            eyre!(
+                "Readback buffer does not match a {}x{} image.",
+                width,
+                height
+            )
+        })
+    }
+
+    fn draw(&mut self, prev: &state::State, state: &state::State, alpha: f32, view: &TextureView) {
+        let colors = state.settings.theme.colors();
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("State Command Encoder"),
+            });
 
-            stg_vertex.copy_to_buffer(&mut encoder, &self.vertex_buffer);
-            stg_index.copy_to_buffer(&mut encoder, &self.index_buffer);
-            num_indices
+        // Positions jump when the state changes (e.g. on serve), so only
+        // interpolate inside a single state.
+        let alpha = if prev.game_state == state.game_state {
+            alpha
         } else {
-            0
+            1.0
+        };
+        let ball = state::Ball {
+            position: util::lerp(prev.ball.position, state.ball.position, alpha),
+            ..state.ball.clone()
+        };
+        let player1 = state::Player {
+            position: util::lerp(prev.player1.position, state.player1.position, alpha),
+            ..state.player1.clone()
+        };
+        let player2 = state::Player {
+            position: util::lerp(prev.player2.position, state.player2.position, alpha),
+            ..state.player2.clone()
         };
 
+        self.instances.clear();
+        if self.atlas.contains("background") {
+            self.instances
+                .push_background(self.atlas.sprite("background", colors.background));
+        }
+        let paddle = self.atlas.sprite("paddle", colors.paddle);
+        self.instances
+            .push_ball(&ball, self.atlas.sprite("ball", colors.ball));
+        self.instances.push_player(&player1, paddle);
+        self.instances.push_player(&player2, paddle);
+        if self.instance_buffer.write(
+            &self.device,
+            &self.queue,
+            bytemuck::cast_slice(self.instances.instances()),
+        ) {
+            self.frame_buffers += 1;
+        }
+        let num_instances = self.instances.instances().len() as u32;
+
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                attachment: &frame.view,
+                attachment: view,
                 resolve_target: None,
-                ops: wgpu::Operations::default(),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: colors.background.x as f64,
+                        g: colors.background.y as f64,
+                        b: colors.background.z as f64,
+                        a: colors.background.w as f64,
+                    }),
+                    store: true,
+                },
             }],
             depth_stencil_attachment: None,
         });
 
-        if num_indices != 0 {
+        let (x, y, width, height) = self.camera.field_rect();
+        if num_instances != 0 && width != 0 && height != 0 {
+            // Keep the ball from showing in the bars once it leaves the field.
+            render_pass.set_scissor_rect(x, y, width, height);
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
             render_pass.set_index_buffer(self.index_buffer.slice(..));
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_indexed(0..num_indices, 0, 0..1);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.atlas_bind_group, &[]);
+            render_pass.draw_indexed(0..UNIT_QUAD_INDICES.len() as u32, 0, 0..num_instances);
         }
 
         drop(render_pass);
-        if state.title_text.visible {
-            draw_text(&state.title_text, &mut self.glyph_brush);
-        }
-        if state.play_button.visible {
-            draw_text(&state.play_button, &mut self.glyph_brush);
-        }
-        if state.quit_button.visible {
-            draw_text(&state.quit_button, &mut self.glyph_brush);
-        }
         if state.player1_score.visible {
-            draw_text(&state.player1_score, &mut self.glyph_brush);
+            draw_text(&state.player1_score, colors.text, &mut self.glyph_brush);
         }
         if state.player2_score.visible {
-            draw_text(&state.player2_score, &mut self.glyph_brush);
+            draw_text(&state.player2_score, colors.text, &mut self.glyph_brush);
+        }
+        if state.rally_text.visible {
+            draw_text(&state.rally_text, colors.text, &mut self.glyph_brush);
         }
         if state.win_text.visible {
-            draw_text(&state.win_text, &mut self.glyph_brush);
+            draw_text(&state.win_text, colors.text, &mut self.glyph_brush);
+        }
+        if state.rules_text.visible {
+            draw_text(&state.rules_text, colors.text, &mut self.glyph_brush);
+        }
+        if state.longest_rally_text.visible {
+            draw_text(
+                &state.longest_rally_text,
+                colors.text,
+                &mut self.glyph_brush,
+            );
+        }
+        if let Some(menu) = state.menus.last() {
+            draw_text(&menu.title, colors.text, &mut self.glyph_brush);
+            for item in &menu.items {
+                draw_text(&item.text, colors.text, &mut self.glyph_brush);
+            }
+        }
+        if let Some(error) = &self.shader_error {
+            draw_text(error, Vec4::one(), &mut self.glyph_brush);
+        }
+        if let Some(counter) = &self.buffer_counter {
+            let count = if counter.is_counting() {
+                self.buffers_created.to_string()
+            } else {
+                format!("{}, wgpu's own not traced", self.buffers_created)
+            };
+            let text = state::Text {
+                position: (10.0, self.height() - 22.0).into(),
+                bounds: (self.width() - 20.0, state::UNBOUNDED_F32).into(),
+                color: colors.text,
+                text: format!("GPU buffers per frame: {}", count),
+                size: 12.0,
+                visible: true,
+                ..Default::default()
+            };
+            draw_text(&text, Vec4::one(), &mut self.glyph_brush);
         }
 
         self.glyph_brush
@@ -315,7 +677,7 @@ impl Renderer {
                 &self.device,
                 &mut self.staging_belt,
                 &mut encoder,
-                &frame.view,
+                view,
                 self.sc_desc.width,
                 self.sc_desc.height,
             )
@@ -323,35 +685,240 @@ impl Renderer {
 
         self.staging_belt.finish();
         self.queue.submit(std::iter::once(encoder.finish()));
-        Ok(())
+
+        // Without a recall the belt never gets its chunks back and creates
+        // new ones every frame.
+        if let Err(e) = self.local_pool.spawner().spawn(self.staging_belt.recall()) {
+            log::error!("Failed to recall the staging belt: {}", e);
+        }
+        self.local_pool.run_until_stalled();
     }
 }
 
-fn draw_text(text: &state::Text, glyph_brush: &mut wgpu_glyph::GlyphBrush<()>) {
+/// Creates the directory the device trace is written to.
+fn trace_dir() -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(TRACE_DIR_NAME);
+    std::fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// With `trace_dir` wgpu records every call of the device in a trace there.
+async fn request_device(
+    instance: &Instance,
+    compatible_surface: Option<&Surface>,
+    trace_dir: Option<&Path>,
+) -> Result<(Adapter, Device, Queue)> {
+    let backend_bit = BackendBit::PRIMARY;
+    println!(
+        "All available adapters that match {:?} backends: {:?}",
+        backend_bit,
+        instance.enumerate_adapters(backend_bit).collect::<Vec<_>>()
+    );
+
+    let adapter = instance
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::Default,
+            compatible_surface,
+        })
+        .await
+        .ok_or_else(|| {
+            eyre!(
+                "Failed to provide adapter for the {:?} backend bit.",
+                backend_bit
+            )
+        })?;
+
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                features: Features::default(),
+                limits: Limits::default(),
+                shader_validation: true,
+            },
+            trace_dir,
+        )
+        .await?;
+    Ok((adapter, device, queue))
+}
+
+fn create_offscreen_texture(device: &Device, sc_desc: &SwapChainDescriptor) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("Offscreen Texture"),
+        size: Extent3d {
+            width: sc_desc.width,
+            height: sc_desc.height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: sc_desc.format,
+        usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::COPY_SRC,
+    })
+}
+
+/// Uploads the atlas image and binds it with a sampler for `shader.frag`.
+fn create_atlas_bind_group(
+    device: &Device,
+    queue: &Queue,
+    layout: &BindGroupLayout,
+    atlas: &Atlas,
+) -> BindGroup {
+    let (width, height) = atlas.image().dimensions();
+    let size = Extent3d {
+        width,
+        height,
+        depth: 1,
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Atlas Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+    });
+    queue.write_texture(
+        TextureCopyView {
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+        },
+        atlas.image().as_raw(),
+        TextureDataLayout {
+            offset: 0,
+            bytes_per_row: width * 4,
+            rows_per_image: height,
+        },
+        size,
+    );
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    // Nearest keeps the pixel art sharp, and never reaches into the padding.
+    let sampler = device.create_sampler(&SamplerDescriptor {
+        label: Some("Atlas Sampler"),
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        address_mode_w: AddressMode::ClampToEdge,
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Nearest,
+        mipmap_filter: FilterMode::Nearest,
+        ..Default::default()
+    });
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Atlas Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&sampler),
+            },
+        ],
+    })
+}
+
+/// Queues `text` with its colour multiplied by `tint`.
+fn draw_text(text: &state::Text, tint: Vec4, glyph_brush: &mut wgpu_glyph::GlyphBrush<()>) {
     let layout = wgpu_glyph::Layout::default().h_align(if text.centered {
         wgpu_glyph::HorizontalAlign::Center
     } else {
         wgpu_glyph::HorizontalAlign::Left
     });
 
-    let section =
-        Section {
-            screen_position: text.position.into(),
-            bounds: text.bounds.into(),
-            layout,
-            ..Section::default()
-        }
-        .add_text(Text::new(&text.text).with_color(text.color).with_scale(
-            if text.focused {
+    let section = Section {
+        screen_position: text.position.into(),
+        bounds: text.bounds.into(),
+        layout,
+        ..Section::default()
+    }
+    .add_text(
+        Text::new(&text.text)
+            .with_color(text.color * tint)
+            .with_scale(if text.focused {
                 text.size + 8.0
             } else {
                 text.size
-            },
-        ));
+            }),
+    );
 
     glyph_brush.queue(section);
 }
 
+/// Builds the pipeline that draws the quads from compiled shaders.
+fn spirv_render_pipeline(
+    device: &Device,
+    pipeline_layout: &PipelineLayout,
+    color_format: TextureFormat,
+    vs_spirv: &[u8],
+    fs_spirv: &[u8],
+) -> RenderPipeline {
+    let vs_module = device.create_shader_module(wgpu::util::make_spirv(vs_spirv));
+    let fs_module = device.create_shader_module(wgpu::util::make_spirv(fs_spirv));
+    create_render_pipeline(
+        device,
+        pipeline_layout,
+        color_format,
+        &[Vertex::DESC, QuadInstance::DESC],
+        vs_module,
+        fs_module,
+    )
+}
+
+/// Compiles the GLSL sources to SPIR-V at runtime, for hot-reloading, and
+/// builds the pipeline that draws the quads.
+#[cfg(feature = "hot-reload")]
+fn compile_render_pipeline(
+    device: &Device,
+    pipeline_layout: &PipelineLayout,
+    color_format: TextureFormat,
+    vs_src: &str,
+    fs_src: &str,
+) -> Result<RenderPipeline> {
+    let mut shader_compiler =
+        shaderc::Compiler::new().ok_or_else(|| eyre!("Failed to create shader compiler."))?;
+    let vs_spirv = shader_compiler
+        .compile_into_spirv(
+            vs_src,
+            shaderc::ShaderKind::Vertex,
+            "shader.vert",
+            SHADER_ENTRY_POINT_NAME,
+            None,
+        )
+        .wrap_err("Failed to compile shader.vert")?;
+    // wgpu panics on a pipeline whose inputs the buffers don't provide.
+    vertex_layout::check_vertex_inputs("shader.vert", vs_src)
+        .map_err(|errors| eyre!("{}", errors.join("\n")))?;
+    let fs_spirv = shader_compiler
+        .compile_into_spirv(
+            fs_src,
+            shaderc::ShaderKind::Fragment,
+            "shader.frag",
+            SHADER_ENTRY_POINT_NAME,
+            None,
+        )
+        .wrap_err("Failed to compile shader.frag")?;
+    Ok(spirv_render_pipeline(
+        device,
+        pipeline_layout,
+        color_format,
+        vs_spirv.as_binary_u8(),
+        fs_spirv.as_binary_u8(),
+    ))
+}
+
+fn present_mode(vsync: bool) -> PresentMode {
+    if vsync {
+        PresentMode::Fifo
+    } else {
+        PresentMode::Immediate
+    }
+}
+
 fn create_render_pipeline(
     device: &Device,
     pipeline_layout: &PipelineLayout,
@@ -383,7 +950,12 @@ fn create_render_pipeline(
         color_states: &[ColorStateDescriptor {
             format: color_format,
             alpha_blend: BlendDescriptor::REPLACE,
-            color_blend: BlendDescriptor::REPLACE,
+            // Sprites may be partly transparent, e.g. a round ball.
+            color_blend: BlendDescriptor {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
+            },
             write_mask: ColorWrite::ALL,
         }],
         depth_stencil_state: None,
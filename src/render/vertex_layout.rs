@@ -0,0 +1,168 @@
+//! Vertex inputs of `shader.vert`. `build.rs` includes this file to check
+//! the shader against them, so it may not use anything from the crate.
+
+// The game and build.rs each use only part of it.
+#![allow(dead_code)]
+
+/// Format of one input, named after the matching `wgpu::VertexFormat`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Float,
+    Float2,
+    Float4,
+}
+
+impl Format {
+    pub const fn size(self) -> u64 {
+        match self {
+            Format::Float => 4,
+            Format::Float2 => 8,
+            Format::Float4 => 16,
+        }
+    }
+
+    /// Type the shader has to declare the input with.
+    pub fn glsl_type(self) -> &'static str {
+        match self {
+            Format::Float => "float",
+            Format::Float2 => "vec2",
+            Format::Float4 => "vec4",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Attribute {
+    pub location: u32,
+    /// Bytes from the start of the struct the input is read from.
+    pub offset: u64,
+    pub format: Format,
+}
+
+/// Inputs read from `Vertex`, once per corner of the unit quad.
+pub const VERTEX: [Attribute; 2] = [
+    Attribute {
+        location: 0,
+        offset: 0,
+        format: Format::Float2,
+    },
+    Attribute {
+        location: 1,
+        offset: 8,
+        format: Format::Float2,
+    },
+];
+
+/// Inputs read from `QuadInstance`, once per drawn entity.
+pub const INSTANCE: [Attribute; 5] = [
+    Attribute {
+        location: 2,
+        offset: 0,
+        format: Format::Float2,
+    },
+    Attribute {
+        location: 3,
+        offset: 8,
+        format: Format::Float2,
+    },
+    Attribute {
+        location: 4,
+        offset: 16,
+        format: Format::Float4,
+    },
+    Attribute {
+        location: 5,
+        offset: 32,
+        format: Format::Float,
+    },
+    Attribute {
+        location: 6,
+        offset: 36,
+        format: Format::Float4,
+    },
+];
+
+/// Every input the vertex shader declares has to be fed by
+/// `Vertex::DESC` or `QuadInstance::DESC`, with a matching type.
+pub fn check_vertex_inputs(name: &str, source: &str) -> std::result::Result<(), Vec<String>> {
+    let provided = || VERTEX.iter().chain(INSTANCE.iter());
+    let mut errors = Vec::new();
+    for (line, text) in source.lines().enumerate() {
+        let (location, glsl_type, input) = match parse_input(text) {
+            Some(input) => input,
+            None => continue,
+        };
+        match provided().find(|attribute| attribute.location == location) {
+            None => errors.push(format!(
+                "{}:{}: error: '{}' reads location {}, which neither Vertex::DESC nor \
+                 QuadInstance::DESC provides (see src/render/vertex_layout.rs)",
+                name,
+                line + 1,
+                input,
+                location
+            )),
+            Some(attribute) if attribute.format.glsl_type() != glsl_type => errors.push(format!(
+                "{}:{}: error: '{}' is declared as {}, but location {} provides a {}",
+                name,
+                line + 1,
+                input,
+                glsl_type,
+                location,
+                attribute.format.glsl_type()
+            )),
+            Some(_) => {}
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses `layout(location = 2) in vec2 i_position;` into its location,
+/// type and name. Other lines give `None`.
+fn parse_input(line: &str) -> Option<(u32, &str, &str)> {
+    let line = line.split("//").next()?.trim();
+    if !line.starts_with("layout") {
+        return None;
+    }
+    let qualifiers = &line[line.find('(')? + 1..line.find(')')?];
+    let mut words = line[line.find(')')? + 1..].split_whitespace();
+    if words.next()? != "in" {
+        return None;
+    }
+    let glsl_type = words.next()?;
+    let name = words.next()?.trim_end_matches(';');
+    let location = qualifiers.split(',').find_map(|qualifier| {
+        let mut parts = qualifier.splitn(2, '=');
+        match (parts.next()?.trim(), parts.next()) {
+            ("location", Some(value)) => value.trim().parse().ok(),
+            _ => None,
+        }
+    })?;
+    Some((location, glsl_type, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = include_str!("../../shaders/shader.vert");
+
+    #[test]
+    fn shipped_shader_reads_provided_inputs() {
+        assert_eq!(check_vertex_inputs("shader.vert", SHADER), Ok(()));
+    }
+
+    #[test]
+    fn unprovided_and_mistyped_inputs_are_errors() {
+        let source = SHADER
+            .replace("layout(location=5) in float", "layout(location=5) in vec2")
+            .replace("layout(location=6)", "layout(location=9)");
+        let errors = check_vertex_inputs("shader.vert", &source).unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].contains("'i_rotation' is declared as vec2"));
+        assert!(errors[1].contains("'i_uv_rect' reads location 9"));
+    }
+}
@@ -0,0 +1,275 @@
+//! Recording and playback of the per-tick input of a session.
+//!
+//! A recording is the RNG seed plus one `Input` snapshot per simulation
+//! tick. Feeding it back through a fresh `Simulation` reproduces the session
+//! exactly. On disk it is stored as:
+//!
+//! | bytes | content                                |
+//! |-------|----------------------------------------|
+//! | 4     | magic `GGRP`                           |
+//! | 2     | format version, little endian          |
+//! | 8     | seed, little endian                    |
+//! | 1     | computer opponent difficulty           |
+//! | 4     | length of the rules, little endian     |
+//! | n     | match rules as TOML                    |
+//! | 4     | tick count, little endian              |
+//! | 3n    | per tick: packed input flags, then the |
+//! |       | player 1 and player 2 axes as `i8`     |
+
+use eyre::*;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::input::Input;
+use crate::rules::MatchRules;
+use crate::sim::Simulation;
+use crate::state::{Difficulty, State};
+use crate::util;
+
+pub const MAGIC: &[u8; 4] = b"GGRP";
+pub const VERSION: u16 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub rules: MatchRules,
+    pub inputs: Vec<Input>,
+}
+
+impl Recording {
+    pub fn new(seed: u64, difficulty: Difficulty, rules: MatchRules) -> Self {
+        Self {
+            seed,
+            difficulty,
+            rules,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, input: &Input) {
+        self.inputs.push(input.clone());
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .wrap_err_with(|| format!("Failed to open replay {}", path.display()))?;
+        Self::read_from(std::io::BufReader::new(file))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .wrap_err_with(|| format!("Failed to create replay {}", path.display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&[difficulty_to_byte(self.difficulty)])?;
+        let rules = self.rules.to_toml()?;
+        writer.write_all(&(rules.len() as u32).to_le_bytes())?;
+        writer.write_all(rules.as_bytes())?;
+        writer.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+        let ticks = self
+            .inputs
+            .iter()
+            .flat_map(|input| {
+                let [p1_axis] = input.p1_axis.to_le_bytes();
+                let [p2_axis] = input.p2_axis.to_le_bytes();
+                vec![input.to_bits(), p1_axis, p2_axis]
+            })
+            .collect::<Vec<_>>();
+        writer.write_all(&ticks)?;
+        Ok(())
+    }
+
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "Not a replay file.");
+
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        ensure!(
+            version == VERSION,
+            "Unsupported replay version {}, expected {}.",
+            version,
+            VERSION
+        );
+
+        let mut seed = [0; 8];
+        reader.read_exact(&mut seed)?;
+        let mut difficulty = [0; 1];
+        reader.read_exact(&mut difficulty)?;
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let rules = read_bytes(&mut reader, u32::from_le_bytes(len).into())
+            .wrap_err("Replay ends inside its rules.")?;
+        let rules = MatchRules::from_toml(std::str::from_utf8(&rules)?)?;
+
+        reader.read_exact(&mut len)?;
+        let ticks = read_bytes(&mut reader, u64::from(u32::from_le_bytes(len)) * 3)
+            .wrap_err("Replay ends before its last tick.")?;
+
+        Ok(Self {
+            seed: u64::from_le_bytes(seed),
+            difficulty: difficulty_from_byte(difficulty[0])?,
+            rules,
+            inputs: ticks
+                .chunks(3)
+                .map(|tick| Input {
+                    p1_axis: i8::from_le_bytes([tick[1]]),
+                    p2_axis: i8::from_le_bytes([tick[2]]),
+                    ..Input::from_bits(tick[0])
+                })
+                .collect(),
+        })
+    }
+
+    /// Runs the whole recording through a fresh simulation without a window
+    /// and returns the final state.
+    pub fn play(&self, width: f32, height: f32) -> State {
+        let mut state = State::new(width, height, self.seed);
+        state.difficulty = self.difficulty;
+        state.rules = self.rules.clone();
+        state.rebinding_locked = true;
+        let mut simulation = Simulation::new(&mut state);
+        let mut events = Vec::new();
+        for input in &self.inputs {
+            simulation.tick(input, &mut state, &mut events, util::TIMESTEP);
+            events.clear();
+        }
+        state
+    }
+}
+
+/// Reads exactly `len` bytes. The buffer only grows with what was actually
+/// read, so a corrupt length can't request a huge allocation.
+fn read_bytes(reader: impl Read, len: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    ensure!(
+        bytes.len() as u64 == len,
+        "Expected {} bytes, found {}.",
+        len,
+        bytes.len()
+    );
+    Ok(bytes)
+}
+
+fn difficulty_to_byte(difficulty: Difficulty) -> u8 {
+    match difficulty {
+        Difficulty::Easy => 0,
+        Difficulty::Normal => 1,
+        Difficulty::Hard => 2,
+    }
+}
+
+fn difficulty_from_byte(byte: u8) -> Result<Difficulty> {
+    match byte {
+        0 => Ok(Difficulty::Easy),
+        1 => Ok(Difficulty::Normal),
+        2 => Ok(Difficulty::Hard),
+        _ => bail!("Unknown difficulty {} in replay.", byte),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::GameState;
+
+    fn recording() -> Recording {
+        let mut recording = Recording::new(42, Difficulty::Hard, MatchRules::default());
+        recording.push(&Input {
+            p1_up_pressed: true,
+            enter_pressed: true,
+            ..Input::default()
+        });
+        recording.push(&Input {
+            p1_axis: -127,
+            p2_axis: 64,
+            ..Input::default()
+        });
+        recording.push(&Input::default());
+        recording
+    }
+
+    fn bytes(recording: &Recording) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        recording.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = recording();
+        let read = Recording::read_from(&bytes(&recording)[..]).unwrap();
+        assert_eq!(read, recording);
+    }
+
+    #[test]
+    fn replays_end_with_the_recorded_score() {
+        let rules = MatchRules {
+            points_to_win: 2,
+            ..MatchRules::default()
+        };
+        let mut recording = Recording::new(3, Difficulty::Hard, rules.clone());
+        let mut state = State::new(800.0, 600.0, recording.seed);
+        state.difficulty = recording.difficulty;
+        state.rules = rules;
+        let mut simulation = Simulation::new(&mut state);
+        let mut events = Vec::new();
+        // Record up to the game over screen, which shows the final score.
+        for tick in 0..60 * 60 {
+            if state.game_state == GameState::GameOver {
+                break;
+            }
+            let input = Input {
+                enter_pressed: tick == 0,
+                p1_up_pressed: tick > 0 && tick / 30 % 2 == 0,
+                ..Input::default()
+            };
+            recording.push(&input);
+            simulation.tick(&input, &mut state, &mut events, util::TIMESTEP);
+            events.clear();
+        }
+        assert_eq!(state.game_state, GameState::GameOver);
+        let score = (state.player1.score, state.player2.score);
+
+        let recording = Recording::read_from(&bytes(&recording)[..]).unwrap();
+        for _ in 0..2 {
+            let replayed = recording.play(800.0, 600.0);
+            assert_eq!((replayed.player1.score, replayed.player2.score), score);
+        }
+    }
+
+    #[test]
+    fn truncated_replay_is_an_error() {
+        let bytes = bytes(&recording());
+        for len in 0..bytes.len() {
+            assert!(Recording::read_from(&bytes[..len]).is_err(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn huge_lengths_are_an_error() {
+        let mut rules = bytes(&recording());
+        // The rules length follows magic, version, seed and difficulty.
+        rules[15..19].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Recording::read_from(&rules[..]).is_err());
+
+        let mut ticks = bytes(&recording());
+        let count = ticks.len() - 3 * 3 - 4;
+        ticks[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Recording::read_from(&ticks[..]).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! Rules a match is played by.
+
+use eyre::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::state::Side;
+use crate::util;
+
+/// Who serves after a point is scored.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServeRule {
+    /// The player who lost the point serves.
+    Loser,
+    /// The player who won the point serves.
+    Winner,
+    /// Players take turns, player 1 serves first.
+    Alternate,
+}
+
+impl std::str::FromStr for ServeRule {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "loser" => Ok(ServeRule::Loser),
+            "winner" => Ok(ServeRule::Winner),
+            "alternate" => Ok(ServeRule::Alternate),
+            _ => Err(eyre!("Unknown serve rule {}", s)),
+        }
+    }
+}
+
+/// Every field is optional in a rules file, missing ones keep the default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatchRules {
+    pub points_to_win: u32,
+    /// The winner needs a lead of two points, so a match can go past
+    /// `points_to_win`.
+    pub win_by_two: bool,
+    /// Seconds between a point and the next serve.
+    pub serve_delay: f32,
+    /// Seconds the game over screen stays up.
+    pub game_over_delay: f32,
+    pub serve: ServeRule,
+    /// Maximum random deviation in radians added to paddle bounces. Zero
+    /// keeps bounces fully deterministic with respect to the hit position.
+    pub bounce_jitter: f32,
+    /// Ball speed in units per second gained with every paddle hit of a
+    /// rally.
+    pub speed_up_per_hit: f32,
+    /// The ball never gets faster than this, in units per second.
+    pub max_ball_speed: f32,
+    /// Turns off spin from moving paddles, the ball flies straight.
+    pub classic_physics: bool,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            points_to_win: 3,
+            win_by_two: false,
+            serve_delay: 2.0,
+            game_over_delay: 1.0,
+            serve: ServeRule::Loser,
+            bounce_jitter: 0.0,
+            speed_up_per_hit: 0.1,
+            max_ball_speed: 3.0,
+            classic_physics: false,
+        }
+    }
+}
+
+impl MatchRules {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read rules {}", path.display()))?;
+        Self::from_toml(&text).wrap_err_with(|| format!("Invalid rules {}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let rules: Self = toml::from_str(text)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    /// Rejects rules no match can be played by, such as a match won with
+    /// zero points or a ball capped below its serve speed.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.points_to_win > 0, "points_to_win must be at least 1.");
+        for (name, value) in &[
+            ("serve_delay", self.serve_delay),
+            ("game_over_delay", self.game_over_delay),
+            ("bounce_jitter", self.bounce_jitter),
+            ("speed_up_per_hit", self.speed_up_per_hit),
+            ("max_ball_speed", self.max_ball_speed),
+        ] {
+            ensure!(
+                value.is_finite() && *value >= 0.0,
+                "{} must be a number of at least 0, got {}.",
+                name,
+                value
+            );
+        }
+        ensure!(
+            self.max_ball_speed >= util::BALL_SPEED,
+            "max_ball_speed must be at least the serve speed {}, got {}.",
+            util::BALL_SPEED,
+            self.max_ball_speed
+        );
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// The side that has won the match with this score, if any.
+    pub fn winner(&self, player1_score: u32, player2_score: u32) -> Option<Side> {
+        let lead = if self.win_by_two { 2 } else { 1 };
+        if player1_score >= self.points_to_win && player1_score >= player2_score + lead {
+            Some(Side::Left)
+        } else if player2_score >= self.points_to_win && player2_score >= player1_score + lead {
+            Some(Side::Right)
+        } else {
+            None
+        }
+    }
+
+    /// Speed of the ball after `hits` paddle hits in a rally.
+    pub fn ball_speed(&self, hits: u32) -> f32 {
+        (util::BALL_SPEED + self.speed_up_per_hit * hits as f32).min(self.max_ball_speed)
+    }
+
+    /// One line summary for the game over screen.
+    pub fn describe(&self) -> String {
+        let mut text = format!("First to {}", self.points_to_win);
+        if self.win_by_two {
+            text.push_str(", win by 2");
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unplayable_rules_are_rejected() {
+        assert!(MatchRules::default().validate().is_ok());
+        for text in &[
+            "points_to_win = 0",
+            "max_ball_speed = 1.0",
+            "serve_delay = -1.0",
+            "speed_up_per_hit = nan",
+        ] {
+            assert!(
+                MatchRules::from_toml(text).is_err(),
+                "{} was accepted",
+                text
+            );
+        }
+        let rules = MatchRules {
+            max_ball_speed: util::BALL_SPEED,
+            ..MatchRules::default()
+        };
+        assert!(rules.validate().is_ok());
+    }
+}
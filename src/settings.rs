@@ -0,0 +1,235 @@
+//! Player settings, changed from the Options menu and kept in a TOML file
+//! between runs:
+//!
+//! ```toml
+//! volume = 0.75
+//! fullscreen = false
+//! vsync = true
+//! width = 1280
+//! height = 720
+//! theme = "classic"
+//!
+//! [rules]
+//! points_to_win = 5
+//! ```
+//!
+//! Key bindings live in their own file, see `bindings`.
+
+use eyre::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::config;
+use crate::math::Vec4;
+use crate::rules::{MatchRules, ServeRule};
+use crate::state::State;
+
+/// Window sizes the Options menu cycles through.
+pub const RESOLUTIONS: &[(u32, u32)] = &[(800, 600), (1280, 720), (1600, 900), (1920, 1080)];
+/// Name of the settings file in the config directory.
+pub const FILE_NAME: &str = "settings.toml";
+const POINTS_TO_WIN: &[u32] = &[3, 5, 7, 11, 21];
+const VOLUME_STEP: f32 = 0.25;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Sound volume from 0 (muted) to 1.
+    pub volume: f32,
+    pub fullscreen: bool,
+    /// Waits for the display refresh before presenting a frame.
+    pub vsync: bool,
+    /// Window size in pixels when not fullscreen.
+    pub width: u32,
+    pub height: u32,
+    pub theme: Theme,
+    /// Rules new matches are played by, unless overridden on the command
+    /// line.
+    pub rules: MatchRules,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            fullscreen: false,
+            vsync: true,
+            width: 1280,
+            height: 720,
+            theme: Theme::Classic,
+            rules: MatchRules::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let mut settings: Self = config::load_or_default(path.as_ref(), "settings");
+        if let Err(error) = settings.rules.validate() {
+            log::warn!("{} Using the default rules.", error);
+            settings.rules = MatchRules::default();
+        }
+        settings
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        config::save(self, path.as_ref())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    /// White text, a light ball and brown paddles on black.
+    Classic,
+    Amber,
+    Green,
+}
+
+/// Colours the renderer draws a theme with.
+#[derive(Debug, Copy, Clone)]
+pub struct ThemeColors {
+    pub background: Vec4,
+    pub ball: Vec4,
+    pub paddle: Vec4,
+    /// Multiplied with the colour of every text.
+    pub text: Vec4,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Classic, Theme::Amber, Theme::Green];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Theme::Classic => "Classic",
+            Theme::Amber => "Amber",
+            Theme::Green => "Green",
+        }
+    }
+
+    pub fn colors(self) -> ThemeColors {
+        match self {
+            Theme::Classic => ThemeColors {
+                background: Vec4::new(0.0, 0.0, 0.0, 1.0),
+                ball: Vec4::new(0.9, 0.85, 0.7, 1.0),
+                paddle: Vec4::new(0.3, 0.2, 0.1, 1.0),
+                text: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            },
+            Theme::Amber => ThemeColors {
+                background: Vec4::new(0.04, 0.02, 0.0, 1.0),
+                ball: Vec4::new(1.0, 0.85, 0.5, 1.0),
+                paddle: Vec4::new(1.0, 0.6, 0.1, 1.0),
+                text: Vec4::new(1.0, 0.7, 0.2, 1.0),
+            },
+            Theme::Green => ThemeColors {
+                background: Vec4::new(0.0, 0.04, 0.02, 1.0),
+                ball: Vec4::new(0.7, 1.0, 0.7, 1.0),
+                paddle: Vec4::new(0.2, 1.0, 0.3, 1.0),
+                text: Vec4::new(0.4, 1.0, 0.5, 1.0),
+            },
+        }
+    }
+}
+
+/// A value the Options menu changes, one step per confirm.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Setting {
+    Volume,
+    Fullscreen,
+    Vsync,
+    Resolution,
+    Theme,
+    PointsToWin,
+    WinByTwo,
+    Serve,
+    Spin,
+}
+
+impl Setting {
+    /// Menu label showing the current value.
+    pub fn label(self, state: &State) -> String {
+        let settings = &state.settings;
+        let on_off = |on: bool| if on { "On" } else { "Off" };
+        match self {
+            Setting::Volume => format!("Volume: {}%", (settings.volume * 100.0).round()),
+            Setting::Fullscreen => format!(
+                "Display: {}",
+                if settings.fullscreen {
+                    "Fullscreen"
+                } else {
+                    "Windowed"
+                }
+            ),
+            Setting::Vsync => format!("VSync: {}", on_off(settings.vsync)),
+            Setting::Resolution => format!("Resolution: {}x{}", settings.width, settings.height),
+            Setting::Theme => format!("Theme: {}", settings.theme.label()),
+            Setting::PointsToWin => format!("Points to win: {}", state.rules.points_to_win),
+            Setting::WinByTwo => format!("Win by two: {}", on_off(state.rules.win_by_two)),
+            Setting::Serve => format!("Serve: {:?}", state.rules.serve),
+            Setting::Spin => format!("Ball spin: {}", on_off(!state.rules.classic_physics)),
+        }
+    }
+
+    /// Steps to the next value. Rules change for the current match as well
+    /// as for the saved settings.
+    pub fn cycle(self, state: &mut State) {
+        let settings = &mut state.settings;
+        match self {
+            Setting::Volume => {
+                settings.volume = if settings.volume >= 1.0 {
+                    0.0
+                } else {
+                    ((settings.volume / VOLUME_STEP).floor() + 1.0) * VOLUME_STEP
+                };
+            }
+            Setting::Fullscreen => settings.fullscreen = !settings.fullscreen,
+            Setting::Vsync => settings.vsync = !settings.vsync,
+            Setting::Resolution => {
+                let current = (settings.width, settings.height);
+                let index = RESOLUTIONS
+                    .iter()
+                    .position(|&size| size == current)
+                    .map_or(0, |i| (i + 1) % RESOLUTIONS.len());
+                let (width, height) = RESOLUTIONS[index];
+                settings.width = width;
+                settings.height = height;
+            }
+            Setting::Theme => settings.theme = next(&Theme::ALL, settings.theme),
+            Setting::PointsToWin => {
+                let points = POINTS_TO_WIN
+                    .iter()
+                    .copied()
+                    .find(|&points| points > state.rules.points_to_win)
+                    .unwrap_or(POINTS_TO_WIN[0]);
+                set_rule(state, |rules| rules.points_to_win = points);
+            }
+            Setting::WinByTwo => {
+                let win_by_two = !state.rules.win_by_two;
+                set_rule(state, |rules| rules.win_by_two = win_by_two);
+            }
+            Setting::Serve => {
+                let serve = next(
+                    &[ServeRule::Loser, ServeRule::Winner, ServeRule::Alternate],
+                    state.rules.serve,
+                );
+                set_rule(state, |rules| rules.serve = serve);
+            }
+            Setting::Spin => {
+                let classic_physics = !state.rules.classic_physics;
+                set_rule(state, |rules| rules.classic_physics = classic_physics);
+            }
+        }
+    }
+}
+
+/// The value after `current` in `values`, wrapping around.
+fn next<T: Copy + PartialEq>(values: &[T], current: T) -> T {
+    let i = values.iter().position(|&v| v == current).unwrap_or(0);
+    values[(i + 1) % values.len()]
+}
+
+fn set_rule(state: &mut State, set: impl Fn(&mut MatchRules)) {
+    set(&mut state.rules);
+    set(&mut state.settings.rules);
+}
@@ -0,0 +1,150 @@
+//! The whole game state as versioned JSON, for Save & Quit and for
+//! comparing two states field by field.
+//!
+//! ```json
+//! { "version": 1, "state": { "ball": { "position": [0.0, 0.0], ... }, ... } }
+//! ```
+
+use eyre::*;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::state::State;
+
+/// Bumped whenever a change to `State` makes older snapshots unreadable.
+pub const VERSION: u32 = 2;
+
+pub fn to_json(state: &State) -> Result<String> {
+    let snapshot = serde_json::json!({
+        "version": VERSION,
+        "state": state,
+    });
+    Ok(serde_json::to_string_pretty(&snapshot)?)
+}
+
+pub fn from_json(text: &str) -> Result<State> {
+    let mut snapshot: Value = serde_json::from_str(text)?;
+    let version = snapshot
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| eyre!("The snapshot has no version."))?;
+    ensure!(
+        version == VERSION as u64,
+        "Snapshot version {} is not supported, expected {}.",
+        version,
+        VERSION
+    );
+    let state = snapshot
+        .as_object_mut()
+        .and_then(|fields| fields.remove("state"))
+        .ok_or_else(|| eyre!("The snapshot has no state."))?;
+    Ok(serde_json::from_value(state)?)
+}
+
+pub fn save(state: &State, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, to_json(state)?)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<State> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    from_json(&text).wrap_err_with(|| format!("Failed to load {}", path.display()))
+}
+
+/// Lists every field that differs between `a` and `b`, one line each, e.g.
+/// `ball.position[1]: 0.25 != 0.3`. Empty when the states are equal.
+pub fn diff(a: &State, b: &State) -> Result<Vec<String>> {
+    let mut differences = Vec::new();
+    diff_values(
+        "",
+        &serde_json::to_value(a)?,
+        &serde_json::to_value(b)?,
+        &mut differences,
+    );
+    Ok(differences)
+}
+
+fn diff_values(path: &str, a: &Value, b: &Value, differences: &mut Vec<String>) {
+    match (a, b) {
+        (Value::Object(a_fields), Value::Object(b_fields)) => {
+            for (key, a_value) in a_fields {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                let b_value = b_fields.get(key).unwrap_or(&Value::Null);
+                diff_values(&field, a_value, b_value, differences);
+            }
+        }
+        (Value::Array(a_items), Value::Array(b_items)) if a_items.len() == b_items.len() => {
+            for (i, (a_item, b_item)) in a_items.iter().zip(b_items).enumerate() {
+                diff_values(&format!("{}[{}]", path, i), a_item, b_item, differences);
+            }
+        }
+        _ if a != b => differences.push(format!("{}: {} != {}", path, a, b)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing() -> State {
+        let mut state = State::new(800.0, 600.0, 42);
+        state.menus.clear();
+        state.game_state = crate::state::GameState::Playing;
+        state.ball.position = (0.25, -0.5).into();
+        state.ball.velocity = (1.5, 0.25).into();
+        state.player1.score = 2;
+        state.rally = 3;
+        state
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let state = playing();
+        let path = std::env::temp_dir().join(format!(
+            "{}-snapshot-{}.json",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        save(&state, &path).unwrap();
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert!(diff(&state, &loaded).unwrap().is_empty());
+        assert_eq!(loaded.checksum(), state.checksum());
+    }
+
+    #[test]
+    fn diff_reports_changed_fields() {
+        let state = playing();
+        let mut changed = state.clone();
+        changed.ball.position.y = 0.5;
+        changed.player2.score = 1;
+        assert_eq!(
+            diff(&state, &changed).unwrap(),
+            vec![
+                String::from("ball.position[1]: -0.5 != 0.5"),
+                String::from("player2.score: 0 != 1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_versions_are_an_error() {
+        let json = to_json(&playing())
+            .unwrap()
+            .replace(&format!("\"version\": {}", VERSION), "\"version\": 0");
+        assert!(from_json(&json).is_err());
+    }
+}
@@ -1,67 +1,390 @@
+use crate::input::{Action, Input};
 use crate::math::{Vec2, Vec4};
+use crate::menu::{Menu, MenuId};
+use crate::rng::Rng;
+use crate::rules::MatchRules;
+use crate::settings::{Setting, Settings};
+#[cfg(feature = "snapshot")]
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub enum GameState {
     MainMenu,
     Serving,
     Playing,
     GameOver,
     Quiting,
-    Base,
+    /// The match is frozen, `State::prev_state` holds the state to resume.
+    Paused,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct State {
     pub ball: Ball,
     pub player1: Player,
     pub player2: Player,
-    pub title_text: Text,
-    pub play_button: Text,
-    pub quit_button: Text,
+    /// Open menus, the last one has the focus. Empty during a match.
+    pub menus: Vec<Menu>,
+    /// Keys bound to every `Action::ALL` entry, as the controls menu shows
+    /// them. Filled in by the window code.
+    pub key_labels: Vec<String>,
+    /// Action waiting for its new key on the controls menu.
+    pub rebinding: Option<Action>,
+    /// Turns the rebind items of the controls menu off. Keys are bound by
+    /// the window code, outside the recorded input, so rebinding is locked
+    /// while recording, replaying and in network matches.
+    #[cfg_attr(feature = "snapshot", serde(skip))]
+    pub rebinding_locked: bool,
+    /// Turns the Resume item of the main menu on. Set by the window code
+    /// when a match left with Save & Quit can be picked up.
+    #[cfg_attr(feature = "snapshot", serde(skip))]
+    pub saved_match: bool,
+    /// Surface size the texts are laid out for.
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec2"))]
+    pub screen_size: Vec2,
     pub player1_score: Text,
     pub player2_score: Text,
+    pub rally_text: Text,
     pub win_text: Text,
+    pub rules_text: Text,
+    pub longest_rally_text: Text,
     pub game_state: GameState,
     pub prev_state: GameState,
+    /// Seconds spent in the current timed state (serving, game over).
+    pub timer: f32,
+    /// Seed `rng` was created from, kept so it can be logged and replayed.
+    pub seed: u64,
+    pub rng: Rng,
+    pub rules: MatchRules,
+    /// Input of the previous tick, used to tell key presses from held keys.
+    pub last_input: Input,
+    /// Difficulty the computer opponent gets in a 1 player match.
+    pub difficulty: Difficulty,
+    /// Computer opponent of the current match, `None` for 2 players.
+    pub ai: Option<Ai>,
+    /// Paddle hits since the last serve.
+    pub rally: u32,
+    /// Longest rally of the current match.
+    pub longest_rally: u32,
+    /// Player settings, not part of a saved match.
+    #[cfg_attr(feature = "snapshot", serde(skip))]
+    pub settings: Settings,
 }
 
+impl State {
+    /// Creates the initial main menu state for a surface of the given size.
+    /// Every random decision of the match is drawn from `seed`.
+    pub fn new(width: f32, height: f32, seed: u64) -> Self {
+        let mut state = Self {
+            ball: Ball {
+                position: (0.0, 0.0).into(),
+                velocity: (0.0, 0.0).into(),
+                spin: 0.0,
+                radius: 0.05,
+                visible: true,
+            },
+            player1: Player {
+                position: (-0.8, 0.0).into(),
+                velocity: (0.0, 0.0).into(),
+                size: (0.05, 0.4).into(),
+                score: 0,
+                visible: true,
+            },
+            player2: Player {
+                position: (0.8, 0.0).into(),
+                velocity: (0.0, 0.0).into(),
+                size: (0.05, 0.4).into(),
+                score: 0,
+                visible: true,
+            },
+            menus: Vec::new(),
+            key_labels: Vec::new(),
+            rebinding: None,
+            rebinding_locked: false,
+            saved_match: false,
+            screen_size: (width, height).into(),
+            player1_score: Text {
+                color: (1.0, 1.0, 1.0, 1.0).into(),
+                text: String::from("0"),
+                size: 32.0,
+                ..Default::default()
+            },
+            player2_score: Text {
+                color: (1.0, 1.0, 1.0, 1.0).into(),
+                text: String::from("0"),
+                size: 32.0,
+                ..Default::default()
+            },
+            rally_text: Text {
+                color: (1.0, 1.0, 1.0, 1.0).into(),
+                text: String::from("Rally 0"),
+                size: 16.0,
+                centered: true,
+                ..Default::default()
+            },
+            win_text: Text {
+                size: 32.0,
+                centered: true,
+                ..Default::default()
+            },
+            rules_text: Text {
+                size: 16.0,
+                centered: true,
+                ..Default::default()
+            },
+            longest_rally_text: Text {
+                size: 16.0,
+                centered: true,
+                ..Default::default()
+            },
+            game_state: GameState::MainMenu,
+            prev_state: GameState::Quiting,
+            timer: 0.0,
+            seed,
+            rng: Rng::new(seed),
+            rules: MatchRules::default(),
+            last_input: Input::default(),
+            difficulty: Difficulty::Normal,
+            ai: None,
+            rally: 0,
+            longest_rally: 0,
+            settings: Settings::default(),
+        };
+        state.layout(width, height);
+        state
+    }
+
+    /// Places the texts that depend on the surface size. Called again
+    /// whenever the surface is resized.
+    pub fn layout(&mut self, width: f32, height: f32) {
+        let bounds = Vec2::new(width, UNBOUNDED_F32);
+
+        self.player1_score.position = (width * 0.25, 20.0).into();
+        self.player2_score.position = (width * 0.75, 20.0).into();
+        self.rally_text.position = (width * 0.5, 20.0).into();
+        self.rally_text.bounds = bounds;
+
+        self.win_text.position = (width * 0.5, height * 0.5).into();
+        self.win_text.bounds = bounds;
+        self.rules_text.position = (width * 0.5, height * 0.5 + 64.0).into();
+        self.rules_text.bounds = bounds;
+        self.longest_rally_text.position = (width * 0.5, height * 0.5 + 96.0).into();
+        self.longest_rally_text.bounds = bounds;
+
+        self.screen_size = (width, height).into();
+        for menu in &mut self.menus {
+            menu.layout(width, height);
+        }
+    }
+
+    /// Puts the menu `id` on top of the open ones.
+    pub fn open_menu(&mut self, id: MenuId) {
+        let mut menu = Menu::new(id, self);
+        menu.layout(self.screen_size.x, self.screen_size.y);
+        self.menus.push(menu);
+    }
+
+    /// Rewrites the key and setting labels of the open menus after either
+    /// changed.
+    pub fn refresh_menus(&mut self) {
+        let mut menus = std::mem::take(&mut self.menus);
+        for menu in &mut menus {
+            menu.update_labels(self);
+        }
+        self.menus = menus;
+    }
+
+    /// Takes what the window code changes outside the simulation over from
+    /// `current`: the surface size and the text layout, the settings and
+    /// the key labels. Called after an older state was restored, so a
+    /// resize or an Options change since isn't undone.
+    pub fn keep_window_state(&mut self, current: &State) {
+        self.settings = current.settings.clone();
+        self.key_labels = current.key_labels.clone();
+        self.rebinding_locked = current.rebinding_locked;
+        self.saved_match = current.saved_match;
+        self.layout(current.screen_size.x, current.screen_size.y);
+        self.refresh_menus();
+    }
+
+    /// Hash of everything that decides how the match goes on, so two peers
+    /// can tell whether their simulations still agree.
+    pub fn checksum(&self) -> u64 {
+        let mut hash = Fnv1a::new();
+        for value in &[
+            self.ball.position.x,
+            self.ball.position.y,
+            self.ball.velocity.x,
+            self.ball.velocity.y,
+            self.ball.spin,
+            self.player1.position.x,
+            self.player1.position.y,
+            self.player2.position.x,
+            self.player2.position.y,
+            self.timer,
+        ] {
+            hash.write(&value.to_bits().to_le_bytes());
+        }
+        for value in &[
+            self.player1.score,
+            self.player2.score,
+            self.rally,
+            self.longest_rally,
+        ] {
+            hash.write(&value.to_le_bytes());
+        }
+        hash.write(&[self.game_state as u8, self.prev_state as u8]);
+        hash.write(&self.rng.state().to_le_bytes());
+        hash.finish()
+    }
+}
+
+/// 64 bit FNV-1a, small and stable across platforms and Rust versions.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct Ball {
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec2"))]
     pub position: Vec2,
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec2"))]
     pub velocity: Vec2,
+    /// Rate in radians per second at which the flight curves toward the
+    /// direction the paddle moved on the last hit.
+    pub spin: f32,
     pub radius: f32,
     pub visible: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct Player {
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec2"))]
     pub position: Vec2,
+    /// Movement of the last tick in units per second.
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec2"))]
+    pub velocity: Vec2,
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec2"))]
     pub size: Vec2,
     pub score: u32,
     pub visible: bool,
 }
 
-impl Player {
-    pub fn contains(&self, ball: &Ball) -> bool {
-        let radii = self.size * 0.5;
-        let min = self.position - radii;
-        let max = self.position + radii;
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Side {
+    Left,
+    Right,
+}
 
-        let b_radii = Vec2 {
-            x: ball.radius,
-            y: ball.radius,
-        };
-        let b_min = ball.position - b_radii;
-        let b_max = ball.position + b_radii;
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
 
-        min.x < b_max.x && max.x > b_min.x && min.y < b_max.y && max.y > b_min.y
+/// How a difficulty level plays.
+#[derive(Debug, Copy, Clone)]
+pub struct AiPreset {
+    /// Seconds between two looks at the ball.
+    pub reaction_time: f32,
+    /// Whether the ball path is followed through wall bounces, instead of
+    /// just chasing the current ball height.
+    pub predict_bounces: bool,
+    /// Largest random offset added to the height the paddle aims for.
+    pub aim_error: f32,
+}
+
+impl Difficulty {
+    pub fn preset(self) -> AiPreset {
+        match self {
+            Difficulty::Easy => AiPreset {
+                reaction_time: 0.3,
+                predict_bounces: false,
+                aim_error: 0.25,
+            },
+            Difficulty::Normal => AiPreset {
+                reaction_time: 0.15,
+                predict_bounces: true,
+                aim_error: 0.12,
+            },
+            Difficulty::Hard => AiPreset {
+                reaction_time: 0.05,
+                predict_bounces: true,
+                aim_error: 0.03,
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(eyre::eyre!("Unknown difficulty {}", s)),
+        }
+    }
+}
+
+/// Computer-controlled paddle.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Ai {
+    pub difficulty: Difficulty,
+    pub side: Side,
+    /// Height the paddle is currently moving towards.
+    pub target_y: f32,
+    /// Seconds left until the ball is looked at again.
+    pub reaction_timer: f32,
+}
+
+impl Ai {
+    pub fn new(difficulty: Difficulty, side: Side) -> Self {
+        Self {
+            difficulty,
+            side,
+            target_y: 0.0,
+            reaction_timer: 0.0,
+        }
     }
 }
 
 pub const UNBOUNDED_F32: f32 = std::f32::INFINITY;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct Text {
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec2"))]
     pub position: Vec2,
+    /// Set by `State::layout`, not saved since JSON has no infinity.
+    #[cfg_attr(feature = "snapshot", serde(skip, default = "unbounded"))]
     pub bounds: Vec2,
+    #[cfg_attr(feature = "snapshot", serde(with = "crate::math::vec4"))]
     pub color: Vec4,
     pub text: String,
     pub size: f32,
@@ -70,6 +393,11 @@ pub struct Text {
     pub centered: bool,
 }
 
+#[cfg(feature = "snapshot")]
+fn unbounded() -> Vec2 {
+    (UNBOUNDED_F32, UNBOUNDED_F32).into()
+}
+
 impl Default for Text {
     fn default() -> Self {
         Self {
@@ -91,4 +419,11 @@ pub enum Event {
     FocusChanged,
     BallBounce(Vec2),
     Score(u32),
+    /// The match should be saved before the game quits.
+    SaveAndQuit,
+    /// The match left with Save & Quit should be loaded.
+    ResumeSavedMatch,
+    /// A setting was changed on the Options menu and should be applied and
+    /// saved.
+    SettingsChanged(Setting),
 }
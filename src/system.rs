@@ -1,8 +1,12 @@
 use crate::any;
+use crate::collision;
 use crate::input;
 use crate::math::Vec2;
+use crate::menu::{MenuAction, MenuId};
+use crate::rules::ServeRule;
 use crate::state::{self, GameState};
 use crate::util;
+use std::cmp::Ordering;
 
 pub fn start_system<S: System>(system: &mut S, state: &mut state::State) {
     system.start(state);
@@ -16,6 +20,7 @@ pub trait System {
         input: &input::Input,
         state: &mut state::State,
         events: &mut Vec<state::Event>,
+        dt: f32,
     );
 }
 
@@ -26,8 +31,15 @@ impl System for VisibilitySystem {
         _input: &input::Input,
         state: &mut state::State,
         _events: &mut Vec<state::Event>,
+        _dt: f32,
     ) {
-        let gs = state.game_state;
+        let paused = state.game_state == GameState::Paused;
+        // A paused match stays on screen behind the pause menu.
+        let gs = if paused {
+            state.prev_state
+        } else {
+            state.game_state
+        };
 
         let is_in_game = any!(
             gs,
@@ -40,15 +52,15 @@ impl System for VisibilitySystem {
         state.player1_score.visible = is_in_game;
         state.player2.visible = is_in_game;
         state.player2_score.visible = is_in_game;
-
-        state.title_text.visible = gs == GameState::MainMenu;
-        state.play_button.visible = gs == GameState::MainMenu;
-        state.quit_button.visible = gs == GameState::MainMenu;
+        state.rally_text.visible = is_in_game && gs != GameState::GameOver;
 
         state.win_text.visible = gs == GameState::GameOver;
+        state.rules_text.visible = gs == GameState::GameOver;
+        state.longest_rally_text.visible = gs == GameState::GameOver;
     }
 }
 
+/// Runs the menus of the main menu screen, submenus included.
 #[derive(Debug)]
 pub struct MenuSystem;
 
@@ -58,8 +70,9 @@ impl System for MenuSystem {
         state.player2.score = 0;
         state.player1.position.y = 0.0;
         state.player2.position.y = 0.0;
-        state.play_button.focused = true;
-        state.quit_button.focused = false;
+        state.longest_rally = 0;
+        state.menus.clear();
+        state.open_menu(MenuId::Main);
     }
 
     fn update_state(
@@ -67,25 +80,147 @@ impl System for MenuSystem {
         input: &input::Input,
         state: &mut state::State,
         events: &mut Vec<state::Event>,
+        _dt: f32,
     ) {
-        if state.play_button.focused && input.ui_down_pressed() {
-            events.push(state::Event::FocusChanged);
-            state.play_button.focused = false;
-            state.quit_button.focused = true;
-        } else if state.quit_button.focused && input.ui_up_pressed() {
-            events.push(state::Event::FocusChanged);
-            state.quit_button.focused = false;
-            state.play_button.focused = true;
+        let action = match update_menus(input, state, events) {
+            Some(action) => action,
+            None => return,
+        };
+        state.prev_state = state::GameState::MainMenu;
+        match action {
+            MenuAction::OnePlayer => {
+                state.ai = Some(state::Ai::new(state.difficulty, state::Side::Right));
+                state.menus.clear();
+                state.game_state = state::GameState::Serving;
+            }
+            MenuAction::TwoPlayers => {
+                state.ai = None;
+                state.menus.clear();
+                state.game_state = state::GameState::Serving;
+            }
+            MenuAction::ResumeSaved => events.push(state::Event::ResumeSavedMatch),
+            MenuAction::Quit => state.game_state = state::GameState::Quiting,
+            _ => {}
         }
+    }
+}
 
-        if state.play_button.focused && input.enter_pressed {
-            events.push(state::Event::ButtonPressed);
-            state.prev_state = state::GameState::MainMenu;
-            state.game_state = state::GameState::Serving;
-        } else if state.quit_button.focused && input.enter_pressed {
-            events.push(state::Event::ButtonPressed);
-            state.prev_state = state::GameState::MainMenu;
-            state.game_state = state::GameState::Quiting;
+/// Runs the menu on top of `state.menus` and handles what every menu does
+/// alike: opening submenus, going back, picking an action to rebind and
+/// changing settings.
+/// Returns any other action picked, including going back from the
+/// bottom menu.
+fn update_menus(
+    input: &input::Input,
+    state: &mut state::State,
+    events: &mut Vec<state::Event>,
+) -> Option<MenuAction> {
+    // the window code binds the next key pressed
+    if state.rebinding.is_some() {
+        return None;
+    }
+    let menu = state.menus.last_mut()?;
+    let action = menu.update(input, events)?;
+    if let MenuAction::Rebind(action) = action {
+        let item = &mut menu.items[menu.focus];
+        item.text.text = format!("{}: press a key", action.label());
+    }
+    match action {
+        MenuAction::Open(id) => state.open_menu(id),
+        MenuAction::Back if state.menus.len() > 1 => {
+            state.menus.pop();
+        }
+        MenuAction::Rebind(action) => state.rebinding = Some(action),
+        MenuAction::Cycle(setting) => {
+            setting.cycle(state);
+            state.refresh_menus();
+            events.push(state::Event::SettingsChanged(setting));
+        }
+        action => return Some(action),
+    }
+    None
+}
+
+/// Drives a paddle for the computer opponent described by `State::ai`.
+///
+/// `update_state` decides where the paddle should go, `apply` then turns
+/// that into the same input flags a human would produce.
+pub struct AiSystem;
+
+impl AiSystem {
+    /// Overwrites the flags of the computer-controlled paddle in `input`.
+    pub fn apply(&self, state: &state::State, input: &mut input::Input) {
+        let ai = match &state.ai {
+            Some(ai) => ai,
+            None => return,
+        };
+        let paddle = match ai.side {
+            state::Side::Left => &state.player1,
+            state::Side::Right => &state.player2,
+        };
+
+        // don't jitter around the target
+        let diff = ai.target_y - paddle.position.y;
+        let dead_zone = paddle.size.y * 0.1;
+        let up = diff > dead_zone;
+        let down = diff < -dead_zone;
+        match ai.side {
+            state::Side::Left => {
+                input.p1_up_pressed = up;
+                input.p1_down_pressed = down;
+                input.p1_axis = 0;
+            }
+            state::Side::Right => {
+                input.p2_up_pressed = up;
+                input.p2_down_pressed = down;
+                input.p2_axis = 0;
+            }
+        }
+    }
+}
+
+impl System for AiSystem {
+    fn update_state(
+        &self,
+        _input: &input::Input,
+        state: &mut state::State,
+        _events: &mut Vec<state::Event>,
+        dt: f32,
+    ) {
+        let (side, preset) = match &mut state.ai {
+            Some(ai) => {
+                ai.reaction_timer -= dt;
+                if ai.reaction_timer > 0.0 {
+                    return;
+                }
+                let preset = ai.difficulty.preset();
+                ai.reaction_timer = preset.reaction_time;
+                (ai.side, preset)
+            }
+            None => return,
+        };
+
+        let paddle_x = match side {
+            state::Side::Left => state.player1.position.x,
+            state::Side::Right => state.player2.position.x,
+        };
+        let ball = &state.ball;
+        let incoming = ball.velocity.x != 0.0
+            && (paddle_x - ball.position.x).signum() == ball.velocity.x.signum();
+
+        let target_y = if !incoming {
+            // wait for the ball in the middle
+            0.0
+        } else if preset.predict_bounces {
+            let time = (paddle_x - ball.position.x) / ball.velocity.x;
+            util::fold_into_field(ball.position.y + ball.velocity.y * time)
+        } else {
+            ball.position.y
+        };
+        let error = state.rng.range(-preset.aim_error, preset.aim_error);
+
+        if let Some(ai) = &mut state.ai {
+            ai.target_y = target_y + error;
         }
     }
 }
@@ -97,20 +232,14 @@ impl System for PlaySystem {
         input: &input::Input,
         state: &mut state::State,
         _events: &mut Vec<state::Event>,
+        dt: f32,
     ) {
+        let player1_start = state.player1.position;
+        let player2_start = state.player2.position;
+
         // move the players
-        if input.p1_up_pressed {
-            state.player1.position.y += util::PLAYER_SPEED;
-        }
-        if input.p1_down_pressed {
-            state.player1.position.y -= util::PLAYER_SPEED;
-        }
-        if input.p2_up_pressed {
-            state.player2.position.y += util::PLAYER_SPEED;
-        }
-        if input.p2_down_pressed {
-            state.player2.position.y -= util::PLAYER_SPEED;
-        }
+        state.player1.position.y += input.p1_movement() * util::PLAYER_SPEED * dt;
+        state.player2.position.y += input.p2_movement() * util::PLAYER_SPEED * dt;
 
         // normalize players
         if state.player1.position.y > 1.0 - state.player1.size.y * 0.5 {
@@ -123,8 +252,14 @@ impl System for PlaySystem {
         } else if state.player2.position.y < state.player1.size.y * 0.5 - 1.0 {
             state.player2.position.y = state.player1.size.y * 0.5 - 1.0;
         }
+        state.player1.velocity = (state.player1.position - player1_start) / dt;
+        state.player2.velocity = (state.player2.position - player2_start) / dt;
 
-        if state.player1.score > 2 || state.player2.score > 2 {
+        if state
+            .rules
+            .winner(state.player1.score, state.player2.score)
+            .is_some()
+        {
             state.prev_state = state::GameState::Playing;
             state.game_state = state::GameState::GameOver;
         }
@@ -139,28 +274,52 @@ impl System for BallSystem {
         _input: &input::Input,
         state: &mut state::State,
         events: &mut Vec<state::Event>,
+        dt: f32,
     ) {
-        // bounce the ball off the players
-        if state.player1.contains(&state.ball) {
-            events.push(state::Event::BallBounce(state.ball.position));
-            state.ball.position.x -= state.ball.velocity.x - state.player1.size.x;
-            state.ball.velocity = util::calc_ball_velocity(&state.ball, &state.player1);
-        } else if state.player2.contains(&state.ball) {
-            events.push(state::Event::BallBounce(state.ball.position));
-            state.ball.position.x -= state.ball.velocity.x + state.player2.size.x;
-            state.ball.velocity.x *= -state.player2.size.y;
-            state.ball.velocity = util::calc_ball_velocity(&state.ball, &state.player2);
-        }
+        curve(&mut state.ball, dt);
 
-        state.ball.position += state.ball.velocity;
-        if state.ball.position.y > 1.0 {
-            events.push(state::Event::BallBounce(state.ball.position));
-            state.ball.position.y = 1.0;
-            state.ball.velocity.y *= -1.0;
-        } else if state.ball.position.y < -1.0 {
+        let mut remaining = 1.0;
+        for _ in 0..MAX_BOUNCES_PER_TICK {
+            let motion = state.ball.velocity * dt * remaining;
+            let (hit, obstacle) = match first_hit(state, motion) {
+                Some(first) => first,
+                None => {
+                    state.ball.position += motion;
+                    break;
+                }
+            };
+
+            state.ball.position += motion * hit.time;
+            remaining *= 1.0 - hit.time;
             events.push(state::Event::BallBounce(state.ball.position));
-            state.ball.position.y = -1.0;
-            state.ball.velocity.y *= -1.0;
+            match obstacle {
+                Obstacle::Paddle(side) => {
+                    let paddle = match side {
+                        state::Side::Left => &state.player1,
+                        state::Side::Right => &state.player2,
+                    };
+                    // Only the face toward the field aims the ball, the
+                    // edges and the back just reflect it.
+                    if hit.normal.x * paddle.position.x < 0.0 {
+                        state.rally += 1;
+                        state.longest_rally = state.longest_rally.max(state.rally);
+                        state.rally_text.text = format!("Rally {}", state.rally);
+                        let speed = state.rules.ball_speed(state.rally);
+                        state.ball.velocity = util::calc_ball_velocity(&state.ball, paddle, speed);
+                        state.ball.spin = if state.rules.classic_physics {
+                            0.0
+                        } else {
+                            paddle.velocity.y * util::SPIN_PER_PADDLE_SPEED
+                        };
+                        jitter_bounce(state);
+                    } else {
+                        state.ball.velocity = collision::reflect(state.ball.velocity, hit.normal);
+                    }
+                }
+                Obstacle::Wall => {
+                    state.ball.velocity = collision::reflect(state.ball.velocity, hit.normal);
+                }
+            }
         }
 
         if state.ball.position.x > 1.0 {
@@ -177,26 +336,99 @@ impl System for BallSystem {
     }
 }
 
-pub struct ServingSystem {
-    last_time: std::time::Instant,
+/// Bends the flight of a spinning ball toward the direction of its spin.
+fn curve(ball: &mut state::Ball, dt: f32) {
+    if ball.spin == 0.0 {
+        return;
+    }
+    // Spin turns a ball flying right counterclockwise and a ball flying
+    // left clockwise, so positive spin always curves upward.
+    let curved = util::rotate(ball.velocity, ball.spin * ball.velocity.x.signum() * dt);
+    if curved.x.abs() < curved.mag() * util::MAX_CURVE_ANGLE.cos() {
+        ball.spin = 0.0;
+        return;
+    }
+    ball.velocity = curved;
+    ball.spin *= (-util::SPIN_DECAY * dt).exp();
 }
 
-impl ServingSystem {
-    pub fn new() -> Self {
-        Self {
-            last_time: std::time::Instant::now(),
-        }
+/// Limits the bounces resolved in one tick, e.g. in a corner between a
+/// paddle and a wall. The rest of the motion of such a tick is dropped.
+const MAX_BOUNCES_PER_TICK: usize = 4;
+
+#[derive(Debug, Copy, Clone)]
+enum Obstacle {
+    Paddle(state::Side),
+    Wall,
+}
+
+/// Earliest thing the ball runs into while moving by `motion`.
+fn first_hit(state: &state::State, motion: Vec2) -> Option<(collision::Hit, Obstacle)> {
+    let ball = &state.ball;
+    let paddles = [
+        (&state.player1, state::Side::Left),
+        (&state.player2, state::Side::Right),
+    ];
+    let paddle_hits = paddles.iter().filter_map(|(paddle, side)| {
+        collision::sweep_circle_aabb(
+            ball.position,
+            ball.radius,
+            motion,
+            paddle.position,
+            paddle.size * 0.5,
+        )
+        .map(|hit| (hit, Obstacle::Paddle(*side)))
+    });
+    // The walls stop the centre of the ball, as `util::fold_into_field`
+    // expects.
+    let walls = [Vec2::unit_y(), -Vec2::unit_y()];
+    let wall_hits = walls.iter().filter_map(|&normal| {
+        collision::sweep_circle_plane(ball.position, 0.0, motion, normal, -1.0)
+            .map(|hit| (hit, Obstacle::Wall))
+    });
+
+    // On a tie the paddle comes first, the wall is hit on the next pass.
+    paddle_hits
+        .chain(wall_hits)
+        .min_by(|a, b| a.0.time.partial_cmp(&b.0.time).unwrap_or(Ordering::Equal))
+}
+
+fn jitter_bounce(state: &mut state::State) {
+    let jitter = state.rules.bounce_jitter;
+    if jitter > 0.0 {
+        let angle = state.rng.range(-jitter, jitter);
+        state.ball.velocity = util::rotate(state.ball.velocity, angle);
     }
 }
 
+pub struct ServingSystem;
+
 impl System for ServingSystem {
     fn start(&mut self, state: &mut state::State) {
-        self.last_time = std::time::Instant::now();
-        let direction = state.ball.position.x.signum();
+        state.timer = 0.0;
+        // the ball is still past the goal line of the player who lost the
+        // point, or in the middle before the first serve. Player 1 serves
+        // toward the right, player 2 toward the left.
+        let player1_serves = match state.rules.serve {
+            ServeRule::Loser => state.ball.position.x < 0.0,
+            ServeRule::Winner => state.ball.position.x >= 0.0,
+            ServeRule::Alternate => (state.player1.score + state.player2.score) & 1 == 0,
+        };
+        let speed = if player1_serves {
+            util::BALL_SPEED
+        } else {
+            -util::BALL_SPEED
+        };
+        let angle = state
+            .rng
+            .range(-util::MAX_SERVE_ANGLE, util::MAX_SERVE_ANGLE);
         state.ball.position = (0.0, 0.0).into();
-        state.ball.velocity = Vec2::unit_x() * direction * -util::BALL_SPEED;
+        state.ball.spin = 0.0;
+        state.ball.velocity = util::rotate(Vec2::unit_x() * speed, angle);
         state.player1_score.text = format!("{}", state.player1.score);
         state.player2_score.text = format!("{}", state.player2.score);
+        state.rally = 0;
+        state.rally_text.text = String::from("Rally 0");
     }
 
     fn update_state(
@@ -204,40 +436,31 @@ impl System for ServingSystem {
         _input: &input::Input,
         state: &mut state::State,
         _events: &mut Vec<state::Event>,
+        dt: f32,
     ) {
-        let current_time = std::time::Instant::now();
-        let delta_time = current_time - self.last_time;
-        if delta_time.as_secs_f32() > 2.0 {
+        state.timer += dt;
+        if state.timer > state.rules.serve_delay {
             state.prev_state = state::GameState::Serving;
             state.game_state = state::GameState::Playing;
         }
     }
 }
 
-pub struct GameOverSystem {
-    last_time: std::time::Instant,
-}
-
-impl GameOverSystem {
-    pub fn new() -> Self {
-        Self {
-            last_time: std::time::Instant::now(),
-        }
-    }
-}
+pub struct GameOverSystem;
 
 impl System for GameOverSystem {
     fn start(&mut self, state: &mut state::State) {
-        self.last_time = std::time::Instant::now();
+        state.timer = 0.0;
 
         state.player1_score.text = format!("{}", state.player1.score);
         state.player2_score.text = format!("{}", state.player2.score);
 
-        state.win_text.text = if state.player1.score > state.player2.score {
-            String::from("Player 1 wins!")
-        } else {
-            String::from("Player 2 wins!")
+        state.win_text.text = match state.rules.winner(state.player1.score, state.player2.score) {
+            Some(state::Side::Left) => String::from("Player 1 wins!"),
+            _ => String::from("Player 2 wins!"),
         };
+        state.rules_text.text = state.rules.describe();
+        state.longest_rally_text.text = format!("Longest rally: {}", state.longest_rally);
     }
 
     fn update_state(
@@ -245,32 +468,452 @@ impl System for GameOverSystem {
         _input: &input::Input,
         state: &mut state::State,
         _events: &mut Vec<state::Event>,
+        dt: f32,
     ) {
-        let current_time = std::time::Instant::now();
-        let delta_time = current_time - self.last_time;
-        if delta_time.as_secs_f32() > 1.0 {
+        state.timer += dt;
+        if state.timer > state.rules.game_over_delay {
             state.prev_state = state::GameState::GameOver;
             state.game_state = state::GameState::MainMenu;
         }
     }
 }
 
-pub struct BaseSystem;
-impl System for BaseSystem {
+/// Freezes a match and runs the pause menu. Systems of the paused state
+/// are not run, so its timers stand still until the game is resumed.
+#[derive(Debug)]
+pub struct PauseSystem;
+
+impl System for PauseSystem {
     fn start(&mut self, state: &mut state::State) {
-        state.game_state = state::GameState::Base;
+        state.prev_state = state.game_state;
+        state.game_state = state::GameState::Paused;
+        state.menus.clear();
+        state.open_menu(MenuId::Pause);
     }
 
     fn update_state(
         &self,
         input: &input::Input,
         state: &mut state::State,
-        _events: &mut Vec<state::Event>,
+        events: &mut Vec<state::Event>,
+        _dt: f32,
     ) {
-        if input.space_pressed && state.game_state == state::GameState::Base {
-            state.game_state = state.prev_state;
-        } else if input.space_pressed {
-            state.game_state = state::GameState::Base;
+        let action = if input.space_pressed {
+            MenuAction::Resume
+        } else {
+            match update_menus(input, state, events) {
+                Some(action) => action,
+                None => return,
+            }
+        };
+        match action {
+            MenuAction::Resume | MenuAction::Back => {
+                state.menus.clear();
+                state.game_state = state.prev_state;
+            }
+            MenuAction::Restart => {
+                state.player1.score = 0;
+                state.player2.score = 0;
+                state.player1.position.y = 0.0;
+                state.player2.position.y = 0.0;
+                state.longest_rally = 0;
+                state.ball.position = (0.0, 0.0).into();
+                state.menus.clear();
+                state.prev_state = state::GameState::Paused;
+                state.game_state = state::GameState::Serving;
+            }
+            MenuAction::MainMenu => {
+                state.prev_state = state::GameState::Paused;
+                state.game_state = state::GameState::MainMenu;
+            }
+            // prev_state keeps the state to resume the saved match in
+            MenuAction::SaveAndQuit => {
+                events.push(state::Event::SaveAndQuit);
+                state.game_state = state::GameState::Quiting;
+            }
+            MenuAction::Quit => state.game_state = state::GameState::Quiting,
+            _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Horizontal direction of the serve after a point ended with the ball
+    /// at `ball_x` and the given score.
+    fn serve(rule: ServeRule, ball_x: f32, score: (u32, u32)) -> f32 {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.rules.serve = rule;
+        state.ball.position = (ball_x, 0.0).into();
+        state.player1.score = score.0;
+        state.player2.score = score.1;
+        ServingSystem.start(&mut state);
+        state.ball.velocity.x.signum()
+    }
+
+    // Player 1 plays on the left, so their serve travels right.
+    const FROM_PLAYER1: f32 = 1.0;
+    const FROM_PLAYER2: f32 = -1.0;
+
+    #[test]
+    fn loser_serves() {
+        // the ball left on the left, player 1 lost the point
+        assert_eq!(serve(ServeRule::Loser, -1.2, (0, 1)), FROM_PLAYER1);
+        assert_eq!(serve(ServeRule::Loser, 1.2, (1, 0)), FROM_PLAYER2);
+    }
+
+    #[test]
+    fn loser_rule_serves_like_the_first_version() {
+        for &x in &[-1.2, 0.0, 1.2] {
+            let baseline = -f32::signum(x);
+            assert_eq!(serve(ServeRule::Loser, x, (0, 0)), baseline);
+        }
+    }
+
+    #[test]
+    fn winner_serves() {
+        assert_eq!(serve(ServeRule::Winner, -1.2, (0, 1)), FROM_PLAYER2);
+        assert_eq!(serve(ServeRule::Winner, 1.2, (1, 0)), FROM_PLAYER1);
+    }
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(
+            (actual - expected).mag() < 1e-4,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn ball_bounces_off_wall_and_paddle_in_one_tick() {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.player1.position = Vec2::new(-0.8, 0.8);
+        state.ball.position = Vec2::new(-0.6, 0.95);
+        state.ball.velocity = Vec2::new(-1.0, 1.0);
+        let mut events = Vec::new();
+        BallSystem.update_state(&input::Input::default(), &mut state, &mut events, 0.25);
+
+        // The top wall after a fifth of the tick, then the face of the left
+        // paddle after another 0.375 of the remaining motion.
+        let bounces: Vec<Vec2> = events
+            .iter()
+            .filter_map(|event| match event {
+                state::Event::BallBounce(position) => Some(*position),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bounces.len(), 2);
+        assert_near(bounces[0], Vec2::new(-0.65, 1.0));
+        assert_near(bounces[1], Vec2::new(-0.725, 0.925));
+
+        // The paddle hit aims the ball, 0.125 above the paddle centre.
+        let angle = std::f32::consts::FRAC_PI_2 * 0.125 / 0.4 * 0.5;
+        let speed = state.rules.ball_speed(1);
+        let velocity = Vec2::new(angle.cos(), angle.sin()) * speed;
+        assert_eq!(state.rally, 1);
+        assert_near(state.ball.velocity, velocity);
+        assert_near(state.ball.position, bounces[1] + velocity * 0.25 * 0.5);
+    }
+
+    const DIFFICULTIES: [state::Difficulty; 3] = [
+        state::Difficulty::Easy,
+        state::Difficulty::Normal,
+        state::Difficulty::Hard,
+    ];
+
+    /// A right hand computer opponent with the ball coming at it through
+    /// a bounce off the top wall.
+    fn ai_state(difficulty: state::Difficulty) -> state::State {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.ai = Some(state::Ai::new(difficulty, state::Side::Right));
+        state.ball.position = Vec2::new(0.0, -0.5);
+        state.ball.velocity = Vec2::new(1.0, 2.0);
+        state
+    }
+
+    /// Ticks between two looks of the computer opponent at the ball.
+    fn reaction_ticks(difficulty: state::Difficulty) -> u32 {
+        let mut state = ai_state(difficulty);
+        let dt = crate::util::TIMESTEP;
+        let input = input::Input::default();
+        AiSystem.update_state(&input, &mut state, &mut Vec::new(), dt);
+        let mut ticks = 1;
+        loop {
+            let timer = state.ai.as_ref().unwrap().reaction_timer;
+            AiSystem.update_state(&input, &mut state, &mut Vec::new(), dt);
+            if state.ai.as_ref().unwrap().reaction_timer > timer {
+                return ticks;
+            }
+            ticks += 1;
+        }
+    }
+
+    #[test]
+    fn ai_reacts_as_fast_as_its_preset() {
+        let ticks: Vec<u32> = DIFFICULTIES.iter().map(|&d| reaction_ticks(d)).collect();
+        for (&difficulty, &ticks) in DIFFICULTIES.iter().zip(&ticks) {
+            let seconds = ticks as f32 * crate::util::TIMESTEP;
+            let reaction_time = difficulty.preset().reaction_time;
+            assert!(
+                (seconds - reaction_time).abs() <= crate::util::TIMESTEP,
+                "{:?} looks every {} s, expected {} s",
+                difficulty,
+                seconds,
+                reaction_time
+            );
+        }
+        assert!(ticks[0] > ticks[1] && ticks[1] > ticks[2], "{:?}", ticks);
+    }
+
+    #[test]
+    fn ai_aims_as_precisely_as_its_preset() {
+        let bounce = crate::util::fold_into_field(-0.5 + 2.0 * 0.8);
+        for &difficulty in &DIFFICULTIES {
+            let preset = difficulty.preset();
+            // Easy only follows the ball, the others know where it bounces.
+            let expected = if preset.predict_bounces { bounce } else { -0.5 };
+            let mut state = ai_state(difficulty);
+            for _ in 0..100 {
+                AiSystem.update_state(&input::Input::default(), &mut state, &mut Vec::new(), 0.0);
+                let ai = state.ai.as_mut().unwrap();
+                assert!(
+                    (ai.target_y - expected).abs() <= preset.aim_error + 1e-5,
+                    "{:?} aims at {}, expected {}",
+                    difficulty,
+                    ai.target_y,
+                    expected
+                );
+                ai.reaction_timer = 0.0;
+            }
+        }
+        assert!(!state::Difficulty::Easy.preset().predict_bounces);
+        let errors: Vec<f32> = DIFFICULTIES.iter().map(|d| d.preset().aim_error).collect();
+        assert!(
+            errors[0] > errors[1] && errors[1] > errors[2],
+            "{:?}",
+            errors
+        );
+    }
+
+    /// Sends the ball straight into the centre of the right paddle's face
+    /// while the paddle moves up at `paddle_speed`.
+    fn hit_right_paddle(state: &mut state::State, paddle_speed: f32) {
+        state.player2.position = Vec2::new(0.8, 0.0);
+        state.player2.velocity = Vec2::new(0.0, paddle_speed);
+        state.ball.position = Vec2::new(0.7, 0.0);
+        state.ball.velocity = Vec2::new(1.0, 0.0);
+        state.ball.spin = 0.0;
+        BallSystem.update_state(&input::Input::default(), state, &mut Vec::new(), 0.1);
+        assert!(state.ball.velocity.x < 0.0, "the paddle was missed");
+    }
+
+    #[test]
+    fn every_hit_speeds_the_ball_up_to_the_cap() {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.rules.speed_up_per_hit = 0.5;
+        state.rules.max_ball_speed = 2.6;
+        // util::BALL_SPEED is 1.5
+        for (hit, &speed) in [2.0, 2.5, 2.6, 2.6].iter().enumerate() {
+            hit_right_paddle(&mut state, 0.0);
+            assert_eq!(state.rally, hit as u32 + 1);
+            assert!(
+                (state.ball.velocity.mag() - speed).abs() < 1e-5,
+                "hit {}: {:?}",
+                hit + 1,
+                state.ball.velocity
+            );
+        }
+    }
+
+    #[test]
+    fn moving_paddles_spin_the_ball() {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        hit_right_paddle(&mut state, 2.0);
+        assert_eq!(state.ball.spin, 2.0 * util::SPIN_PER_PADDLE_SPEED);
+        hit_right_paddle(&mut state, -2.0);
+        assert_eq!(state.ball.spin, -2.0 * util::SPIN_PER_PADDLE_SPEED);
+        hit_right_paddle(&mut state, 0.0);
+        assert_eq!(state.ball.spin, 0.0);
+
+        // Upward spin curves the ball upward on the way back.
+        hit_right_paddle(&mut state, 2.0);
+        BallSystem.update_state(&input::Input::default(), &mut state, &mut Vec::new(), 0.1);
+        assert!(state.ball.velocity.y > 0.0, "{:?}", state.ball.velocity);
+    }
+
+    #[test]
+    fn classic_physics_has_no_spin() {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.rules.classic_physics = true;
+        hit_right_paddle(&mut state, 2.0);
+        assert_eq!(state.ball.spin, 0.0);
+        BallSystem.update_state(&input::Input::default(), &mut state, &mut Vec::new(), 0.1);
+        assert_eq!(state.ball.velocity.y, 0.0);
+    }
+
+    fn confirm_first_controls_item(locked: bool) -> state::State {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.rebinding_locked = locked;
+        state.menus.clear();
+        state.open_menu(MenuId::Controls);
+        let confirm = input::Input {
+            enter_pressed: true,
+            ..input::Input::default()
+        };
+        update_menus(&confirm, &mut state, &mut Vec::new());
+        state
+    }
+
+    #[test]
+    fn controls_menu_rebinds() {
+        let state = confirm_first_controls_item(false);
+        assert_eq!(state.rebinding, Some(input::Action::P1Up));
+    }
+
+    #[test]
+    fn locked_controls_menu_never_waits_for_a_key() {
+        let state = confirm_first_controls_item(true);
+        assert_eq!(state.rebinding, None);
+    }
+
+    /// The parts of a match a pause must not change.
+    fn frozen(state: &state::State) -> impl PartialEq + std::fmt::Debug {
+        (
+            state.game_state,
+            state.timer,
+            state.ball.position,
+            state.ball.velocity,
+            state.player1.position,
+            state.player2.position,
+            (state.player1.score, state.player2.score),
+            state.rng.state(),
+        )
+    }
+
+    #[test]
+    fn pause_stops_the_countdowns_and_resume_continues_them() {
+        let dt = crate::util::TIMESTEP;
+        let space = input::Input {
+            space_pressed: true,
+            ..input::Input::default()
+        };
+        let idle = input::Input::default();
+        for &countdown in &[state::GameState::Serving, state::GameState::GameOver] {
+            let mut state = state::State::new(800.0, 600.0, 1);
+            let mut simulation = crate::sim::Simulation::new(&mut state);
+            simulation.start_match(&mut state);
+            if countdown == state::GameState::GameOver {
+                state.player1.score = state.rules.points_to_win;
+                state.game_state = state::GameState::GameOver;
+                GameOverSystem.start(&mut state);
+            }
+            let mut events = Vec::new();
+            for _ in 0..10 {
+                simulation.tick(&idle, &mut state, &mut events, dt);
+            }
+            let before = frozen(&state);
+
+            simulation.tick(&space, &mut state, &mut events, dt);
+            assert_eq!(state.game_state, state::GameState::Paused);
+            // Far longer than the serve and game over delays.
+            for _ in 0..60 * 5 {
+                simulation.tick(&idle, &mut state, &mut events, dt);
+            }
+            assert_eq!(state.game_state, state::GameState::Paused);
+
+            simulation.tick(&space, &mut state, &mut events, dt);
+            assert_eq!(frozen(&state), before, "{:?}", countdown);
+            assert!(state.menus.is_empty());
+            simulation.tick(&idle, &mut state, &mut events, dt);
+            assert!(state.timer > dt * 10.0, "{:?}", countdown);
+        }
+    }
+
+    /// Moves the focus of the top menu one item down or up and returns the
+    /// label of the item focused then.
+    fn step_focus(state: &mut state::State, down: bool) -> String {
+        let input = input::Input {
+            p1_down_pressed: down,
+            p1_up_pressed: !down,
+            ..input::Input::default()
+        };
+        update_menus(&input, state, &mut Vec::new());
+        let menu = state.menus.last().unwrap();
+        menu.items[menu.focus].text.text.clone()
+    }
+
+    #[test]
+    fn menu_focus_wraps_and_skips_disabled_items() {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        MenuSystem.start(&mut state);
+        assert_eq!(step_focus(&mut state, false), "Quit");
+        assert_eq!(step_focus(&mut state, true), "1 Player");
+        assert_eq!(step_focus(&mut state, true), "2 Players");
+        // Resume is disabled without a saved match.
+        assert_eq!(step_focus(&mut state, true), "Options");
+        assert_eq!(step_focus(&mut state, false), "2 Players");
+
+        // With every rebind item disabled only Back is left to focus.
+        state.rebinding_locked = true;
+        state.open_menu(MenuId::Controls);
+        assert_eq!(step_focus(&mut state, true), "Back");
+        assert_eq!(step_focus(&mut state, false), "Back");
+    }
+
+    fn main_menu_items(saved_match: bool) -> Vec<(Option<MenuAction>, bool)> {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.saved_match = saved_match;
+        MenuSystem.start(&mut state);
+        state.menus[0]
+            .items
+            .iter()
+            .map(|item| (item.action, item.disabled))
+            .collect()
+    }
+
+    #[test]
+    fn resume_is_offered_only_with_a_saved_match() {
+        let resume = |saved_match| {
+            main_menu_items(saved_match)
+                .into_iter()
+                .find(|(action, _)| *action == Some(MenuAction::ResumeSaved))
+                .map(|(_, disabled)| disabled)
+        };
+        assert_eq!(resume(false), Some(true));
+        assert_eq!(resume(true), Some(false));
+    }
+
+    #[test]
+    fn picking_resume_asks_for_the_saved_match() {
+        let mut state = state::State::new(800.0, 600.0, 1);
+        state.saved_match = true;
+        MenuSystem.start(&mut state);
+        let menu = &mut state.menus[0];
+        let resume = menu
+            .items
+            .iter()
+            .position(|item| item.action == Some(MenuAction::ResumeSaved))
+            .unwrap();
+        menu.focus = resume;
+        let confirm = input::Input {
+            enter_pressed: true,
+            ..input::Input::default()
+        };
+        let mut events = Vec::new();
+        MenuSystem.update_state(&confirm, &mut state, &mut events, 0.0);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, state::Event::ResumeSavedMatch)));
+        assert_eq!(state.game_state, state::GameState::MainMenu);
+    }
+
+    #[test]
+    fn alternate_starts_with_player1() {
+        assert_eq!(serve(ServeRule::Alternate, 0.0, (0, 0)), FROM_PLAYER1);
+        assert_eq!(serve(ServeRule::Alternate, -1.2, (0, 1)), FROM_PLAYER2);
+        assert_eq!(serve(ServeRule::Alternate, 1.2, (1, 1)), FROM_PLAYER1);
+    }
+}
@@ -3,18 +3,59 @@
 use crate::math::Vec2;
 use crate::state::{Ball, Player};
 
-pub const PLAYER_SPEED: f32 = 0.05;
-pub const BALL_SPEED: f32 = 0.025;
+/// Length of one simulation step in seconds.
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+/// Upper bound for the time fed into the accumulator per frame, so a long
+/// stall doesn't make the simulation spiral trying to catch up.
+pub const MAX_FRAME_TIME: f32 = 0.25;
+
+/// Paddle speed in units per second.
+pub const PLAYER_SPEED: f32 = 3.0;
+/// Ball speed in units per second when served.
+pub const BALL_SPEED: f32 = 1.5;
+
+/// Spin in radians per second the ball gets per unit per second of paddle
+/// movement at the hit.
+pub const SPIN_PER_PADDLE_SPEED: f32 = 0.3;
+/// Share of its spin the ball loses per second.
+pub const SPIN_DECAY: f32 = 1.0;
+/// Spin stops curving the ball once it flies this steep.
+pub const MAX_CURVE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
 
 const BOUNCE_ANGLE: f32 = std::f32::consts::FRAC_PI_2;
+/// Largest angle from the x-axis a serve can take.
+pub const MAX_SERVE_ANGLE: f32 = std::f32::consts::FRAC_PI_6;
 
-pub fn calc_ball_velocity(ball: &Ball, player: &Player) -> Vec2 {
+pub fn calc_ball_velocity(ball: &Ball, player: &Player, speed: f32) -> Vec2 {
     let diff_y = ball.position.y - player.position.y;
     let ratio = diff_y / player.size.y * 0.5;
     Vec2 {
         x: (BOUNCE_ANGLE * ratio).cos() * -player.position.x.signum(),
         y: (BOUNCE_ANGLE * ratio).sin(),
-    } * BALL_SPEED
+    } * speed
+}
+
+pub fn rotate(v: Vec2, angle: f32) -> Vec2 {
+    let (sin, cos) = angle.sin_cos();
+    Vec2 {
+        x: v.x * cos - v.y * sin,
+        y: v.x * sin + v.y * cos,
+    }
+}
+
+/// Maps a height the ball would reach without walls to the height it has
+/// after bouncing between the walls at -1 and 1.
+pub fn fold_into_field(y: f32) -> f32 {
+    let y = (y + 1.0).rem_euclid(4.0);
+    if y > 2.0 {
+        3.0 - y
+    } else {
+        y - 1.0
+    }
+}
+
+pub fn lerp(prev: Vec2, current: Vec2, alpha: f32) -> Vec2 {
+    prev + (current - prev) * alpha
 }
 
 pub fn size_of_slice<T: Sized>(slice: &[T]) -> usize {
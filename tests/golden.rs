@@ -0,0 +1,21 @@
+//! Renders every golden scene headless and compares it with
+//! `tests/golden/<scene>.png`. Run with `GNIP_GNOP_BLESS=1` to write the
+//! goldens instead, after checking that the change to the frames is
+//! intended. Without any GPU adapter the check is skipped.
+
+use eyre::*;
+use gnip_gnop::render::golden;
+use std::path::Path;
+
+#[test]
+fn screens_match_goldens() -> Result<()> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let bless = std::env::var_os("GNIP_GNOP_BLESS").is_some();
+    if !futures::executor::block_on(golden::adapter_available()) {
+        eprintln!("Skipping the golden check, no GPU adapter is available.");
+        return Ok(());
+    }
+    futures::executor::block_on(golden::run(&dir, bless)).wrap_err(
+        "Rendered frames don't match tests/golden, rerun with GNIP_GNOP_BLESS=1 to accept them",
+    )
+}